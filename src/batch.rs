@@ -0,0 +1,211 @@
+use std::{
+    fmt::{self, Display},
+    io::Write,
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::{board::Scenario, events::EventBus, headless::HeadlessConfig};
+
+/// Settings of repeated headless runs of one scenario
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Scenario, duration and time step of every run. The seed, or the one of the
+    /// scenario, is the seed of the first run and counted up for the others.
+    pub run: HeadlessConfig,
+    pub runs: u32,
+    pub csv: Option<PathBuf>,
+    pub json: Option<PathBuf>,
+}
+
+/// KPIs of a single run
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResult {
+    pub seed: u64,
+    pub completed: u32,
+    /// Completed products per minute
+    pub throughput: f32,
+    /// In seconds, `None` if no product was completed
+    pub average_lead_time: Option<f32>,
+    pub average_wip: f32,
+}
+
+/// Spread of one KPI over all runs
+#[derive(Debug, Clone, Serialize)]
+pub struct Statistic {
+    pub kpi: &'static str,
+    /// Runs that had a value for the KPI
+    pub samples: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Bounds of the 95% confidence interval of the mean
+    pub ci95_low: f64,
+    pub ci95_high: f64,
+}
+
+impl Statistic {
+    fn new(kpi: &'static str, values: &[f64]) -> Self {
+        let n = values.len();
+        let mean = if n > 0 {
+            values.iter().sum::<f64>() / n as f64
+        } else {
+            f64::NAN
+        };
+        let std_dev = if n > 1 {
+            let variance = values
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f64>()
+                / (n - 1) as f64;
+            variance.sqrt()
+        } else {
+            0.0
+        };
+        let half_width = if n > 1 {
+            student_t_975(n - 1) * std_dev / (n as f64).sqrt()
+        } else {
+            0.0
+        };
+        Self {
+            kpi,
+            samples: n,
+            mean,
+            std_dev,
+            ci95_low: mean - half_width,
+            ci95_high: mean + half_width,
+        }
+    }
+}
+
+/// Quantile of the Student t distribution for a two-sided 95% interval
+fn student_t_975(degrees_of_freedom: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    TABLE.get(degrees_of_freedom - 1).copied().unwrap_or(1.96)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub scenario: String,
+    pub duration: f32,
+    pub runs: Vec<RunResult>,
+    pub summary: Vec<Statistic>,
+}
+
+/// Run the scenario `config.runs` times with consecutive seeds
+pub fn run(scenarios: &[Scenario], config: &BatchConfig) -> Result<BatchResult, String> {
+    let scenario = scenarios
+        .get(config.run.scenario.wrapping_sub(1))
+        .ok_or_else(|| format!("There is no scenario {}", config.run.scenario))?;
+    let first_seed = config.run.seed.unwrap_or(scenario.seed);
+
+    let mut runs = Vec::new();
+    for seed in (first_seed..).take(config.runs as usize) {
+        let run_config = HeadlessConfig {
+            seed: Some(seed),
+            ..config.run.clone()
+        };
+        let board = crate::headless::run_config(scenarios, &run_config, EventBus::default(), None)?;
+        let metrics = &board.metrics;
+        runs.push(RunResult {
+            seed,
+            completed: metrics.completed,
+            throughput: metrics.throughput(),
+            average_lead_time: metrics
+                .average_lead_time()
+                .map(|lead_time| lead_time.as_secs_f32()),
+            average_wip: metrics.average_wip(),
+        });
+    }
+
+    let kpi = |value: fn(&RunResult) -> Option<f32>| {
+        runs.iter()
+            .filter_map(value)
+            .map(f64::from)
+            .collect::<Vec<_>>()
+    };
+    let summary = vec![
+        Statistic::new("completed", &kpi(|run| Some(run.completed as f32))),
+        Statistic::new("throughput", &kpi(|run| Some(run.throughput))),
+        Statistic::new("average_lead_time", &kpi(|run| run.average_lead_time)),
+        Statistic::new("average_wip", &kpi(|run| Some(run.average_wip))),
+    ];
+    Ok(BatchResult {
+        scenario: scenario.name.clone(),
+        duration: config.run.duration.as_secs_f32(),
+        runs,
+        summary,
+    })
+}
+
+impl BatchResult {
+    pub fn statistic(&self, kpi: &str) -> Option<&Statistic> {
+        self.summary.iter().find(|statistic| statistic.kpi == kpi)
+    }
+    /// One line per run followed by the summary lines
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "seed,completed,throughput,average_lead_time,average_wip"
+        )?;
+        for run in &self.runs {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                run.seed,
+                run.completed,
+                run.throughput,
+                run.average_lead_time
+                    .map_or(String::new(), |lead_time| lead_time.to_string()),
+                run.average_wip
+            )?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "kpi,samples,mean,std_dev,ci95_low,ci95_high")?;
+        for statistic in &self.summary {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                statistic.kpi,
+                statistic.samples,
+                statistic.mean,
+                statistic.std_dev,
+                statistic.ci95_low,
+                statistic.ci95_high
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, writer: impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+}
+
+impl Display for BatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} runs of {} over {:.0}s each:",
+            self.runs.len(),
+            self.scenario,
+            self.duration
+        )?;
+        for statistic in &self.summary {
+            writeln!(
+                f,
+                "  {:<18} mean {:>8.2}  std dev {:>7.2}  95% CI [{:.2}, {:.2}]",
+                statistic.kpi,
+                statistic.mean,
+                statistic.std_dev,
+                statistic.ci95_low,
+                statistic.ci95_high
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -2,19 +2,33 @@ use glam::{Vec2, vec2};
 #[cfg(target_arch = "x86_64")]
 use macroquad::prelude::{Color, GREEN, draw_circle, draw_text, request_new_screen_size};
 use palette::Srgb;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::array::from_fn;
 use std::ops::Index;
 use std::ops::IndexMut;
+use std::path::Path;
 use std::time::Duration;
 
 use crate::constants::*;
+use crate::dispatching::DispatchRule;
+use crate::event_queue::{EventQueue, ProductEvent};
+use crate::events::{Event, EventBus, EventKind};
+use crate::metrics::{self, Metrics};
+use crate::orders::{Order, OrderBook, ReleasePolicy};
+use crate::product::Activity;
 use crate::product::Product;
 use crate::product::ProductPlan;
+use crate::reliability::{Breakdowns, Reliability};
+use crate::routing::Layout;
+use crate::time_manager::ClockSource;
 use crate::time_manager::TimeManager;
 use crate::time_manager::VirtualInstant;
 // use crate::
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ModuleState {
     Functional,
     Maintaining,
@@ -26,6 +40,8 @@ pub struct Module {
     pub in_production: u32,
     pub in_storage: u32,
     pub max_production: u32,
+    /// Shows the products it holds on its LEDs, whatever its capacity
+    pub storage: bool,
     pub brightness_x: [Srgb; LEDS_PER_DIR],
     pub brightness_y: [Srgb; LEDS_PER_DIR],
     pub state: ModuleState,
@@ -38,6 +54,7 @@ impl Module {
             in_production: 0,
             in_storage: 0,
             max_production: 1,
+            storage: false,
             brightness_x: [color; LEDS_PER_DIR],
             brightness_y: [color; LEDS_PER_DIR],
             state: ModuleState::Functional,
@@ -135,15 +152,16 @@ impl Module {
         self.brightness_x[1..LEDS_PER_DIR - 1].fill(color);
         self.brightness_y[1..LEDS_PER_DIR - 1].fill(color);
     }
-    fn is_storage(&self) -> bool {
-        self.max_production > 1
+    pub fn is_storage(&self) -> bool {
+        self.storage
     }
     pub fn draw_as_storage(&mut self) {
         let middle = LEDS_PER_DIR / 2;
 
         let middle_color = self.brightness_x[middle];
 
-        for in_storage in 0..=self.in_storage {
+        // A storage holding more products than it can show stays full
+        for in_storage in 0..=self.in_storage.min(STORAGE_LED_CAPACITY) {
             match in_storage {
                 0 => {}
                 1 => {} // middle is already lit
@@ -190,86 +208,260 @@ pub fn draw_led_strip(start: Vec2, end: Vec2, colors: [Srgb; LEDS_PER_DIR]) {
     }
 }
 
+/// Point in time a machine state change is measured from
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    /// Start of the scenario
+    #[default]
+    Scenario,
+    /// Start of the phase declaring the change
+    Phase,
+}
+
 #[derive(Clone)]
 pub struct MachineStateChange {
-    time: Duration,
+    pub time: Duration,
+    pub anchor: Anchor,
+    pub state: ModuleState,
+    pub pos: [i32; 2],
+    /// Revert the machine to `Functional` after this time
+    pub duration: Option<Duration>,
+    /// Happen again after this period for as long as the declaring phase is active
+    pub repeat: Option<Duration>,
+}
+
+/// Machine state change waiting for its time to come
+#[derive(Clone, Serialize, Deserialize)]
+struct ArmedStateChange {
+    at: VirtualInstant,
     state: ModuleState,
     pos: [i32; 2],
+    duration: Option<Duration>,
+    repeat: Option<Duration>,
+    /// Phase the repetitions belong to, `None` for reverts that always happen
+    phase: Option<usize>,
 }
-impl MachineStateChange {
-    pub fn new(time: Duration, state: ModuleState, pos: [i32; 2]) -> Self {
-        Self { time, state, pos }
-    }
+
+/// Timed part of a scenario with its own product plans and machine state changes
+#[derive(Clone)]
+pub struct Phase {
+    pub name: String,
+    /// `None` keeps the phase running forever, only allowed for the last phase
+    pub duration: Option<Duration>,
+    pub product_plans: Vec<ProductPlan>,
+    /// Armed when the phase starts
+    pub machine_state_changes: Vec<MachineStateChange>,
 }
 
 #[derive(Clone)]
 pub struct Scenario {
     pub name: String,
-    pub starting_steps: Vec<ProductPlan>,
-    pub disturbance_steps: Vec<ProductPlan>,
-    pub starting_time: VirtualInstant,
-    pub pre_duration: Duration,
-    pub disturbance_duration: Duration,
-    pub state: ScenarioState,
-    pub machine_state_changes: Vec<MachineStateChange>,
+    pub phases: Vec<Phase>,
+    /// Modules that fail at random while the scenario runs
+    pub reliability: Vec<Reliability>,
+    /// Seed of the random generator of the board, the same seed gives the same run
+    pub seed: u64,
+    /// Used to route products to alternative machines
+    pub layout: Layout,
+    pub dispatch_rule: DispatchRule,
+    /// Products made on top of the endless ones of the phase plans
+    pub orders: Vec<Order>,
+    pub release_policy: ReleasePolicy,
+    current_phase: usize,
+    phase_start: VirtualInstant,
+    /// State changes of the phases started so far that did not happen yet
+    armed_machine_state_changes: Vec<ArmedStateChange>,
 }
 impl Scenario {
-    pub fn starting_scenario() -> Scenario {
-        Self {
-            name: "Starting Scenario".to_string(),
-            starting_steps: vec![STEPS_TOP_NORMAL.clone(), STEPS_BOTTOM_NORMAL.clone()],
-            disturbance_steps: vec![STEPS_TOP_NORMAL.clone(), STEPS_BOTTOM_NORMAL.clone()],
-            starting_time: VirtualInstant::zero(),
-            pre_duration: Duration::from_secs(1_000_000),
-            disturbance_duration: Duration::from_secs(1_000_000),
-            state: ScenarioState::Start,
-            machine_state_changes: Vec::new(),
+    pub fn new(name: String, phases: Vec<Phase>) -> Self {
+        assert!(!phases.is_empty(), "A scenario needs at least one phase");
+        let mut scenario = Self {
+            name,
+            phases,
+            reliability: Vec::new(),
+            seed: 0,
+            layout: Layout::default(),
+            dispatch_rule: DispatchRule::default(),
+            orders: Vec::new(),
+            release_policy: ReleasePolicy::default(),
+            current_phase: 0,
+            phase_start: VirtualInstant::zero(),
+            armed_machine_state_changes: Vec::new(),
+        };
+        scenario.arm_phase();
+        scenario
+    }
+    /// Scenario without any product plans, used until a real one is loaded
+    pub fn idle() -> Scenario {
+        Self::new(
+            "Idle".to_string(),
+            vec![Phase {
+                name: "Idle".to_string(),
+                duration: None,
+                product_plans: Vec::new(),
+                machine_state_changes: Vec::new(),
+            }],
+        )
+    }
+    /// All product plans the scenario can run in any of its phases or orders
+    pub fn product_plans(&self) -> impl Iterator<Item = &ProductPlan> {
+        self.phases
+            .iter()
+            .flat_map(|phase| &phase.product_plans)
+            .chain(self.orders.iter().map(|order| &order.plan))
+    }
+    /// Stretch the production time of every step of every plan by `factor`
+    pub fn scale_step_times(&mut self, factor: f32) {
+        let plans = self
+            .phases
+            .iter_mut()
+            .flat_map(|phase| &mut phase.product_plans)
+            .chain(self.orders.iter_mut().map(|order| &mut order.plan));
+        for plan in plans {
+            for step in &mut plan.steps {
+                step.scale_production_time(factor);
+            }
         }
     }
     fn current_steps(&self) -> Vec<ProductPlan> {
-        match self.state {
-            ScenarioState::Start => self.starting_steps.clone(),
-            ScenarioState::Disturbtion => self.disturbance_steps.clone(),
-            ScenarioState::End => self.starting_steps.clone(),
+        self.phases[self.current_phase].product_plans.clone()
+    }
+    /// Fraction of the timed phases that has passed
+    pub fn progress(&self, now: VirtualInstant) -> f32 {
+        let total = self
+            .phases
+            .iter()
+            .filter_map(|phase| phase.duration)
+            .sum::<Duration>();
+        if total.is_zero() {
+            return 0.0;
         }
+        (now.inner().as_secs_f32() / total.as_secs_f32()).min(1.0)
     }
-    fn current_state_durration(&self) -> Duration {
-        match self.state {
-            ScenarioState::Start => self.pre_duration,
-            ScenarioState::Disturbtion => self.disturbance_duration,
-            ScenarioState::End => Duration::from_secs(1_000_000),
+    /// Name of the current phase, used to split the metrics
+    pub fn phase_name(&self) -> &str {
+        &self.phases[self.current_phase].name
+    }
+    pub fn current_phase(&self) -> usize {
+        self.current_phase
+    }
+    /// Arm the state changes declared by the current phase. A change anchored to
+    /// the scenario at a time the phase had not started yet happens at its start.
+    fn arm_phase(&mut self) {
+        for change in &self.phases[self.current_phase].machine_state_changes {
+            let start = match change.anchor {
+                Anchor::Scenario => VirtualInstant::zero(),
+                Anchor::Phase => self.phase_start,
+            };
+            self.armed_machine_state_changes.push(ArmedStateChange {
+                at: (start + change.time).max(self.phase_start),
+                state: change.state.clone(),
+                pos: change.pos,
+                duration: change.duration,
+                repeat: change.repeat,
+                phase: Some(self.current_phase),
+            });
         }
     }
+    /// End of the current phase or next armed state change, whichever comes first
+    fn next_change(&self) -> Option<VirtualInstant> {
+        let phase_end = self.phases[self.current_phase]
+            .duration
+            .filter(|_| self.current_phase + 1 < self.phases.len())
+            .map(|duration| self.phase_start + duration);
+        self.armed_machine_state_changes
+            .iter()
+            .map(|change| change.at)
+            .chain(phase_end)
+            .min()
+    }
     #[must_use]
-    fn update(&mut self, time: &TimeManager) -> Vec<MachineStateChange> {
-        let elapsed = (time.now() - self.starting_time).inner();
-        if elapsed >= self.current_state_durration() {
-            self.starting_time = time.now();
-            self.state = match self.state {
-                ScenarioState::Start => ScenarioState::Disturbtion,
-                ScenarioState::Disturbtion => ScenarioState::End,
-                ScenarioState::End => ScenarioState::End,
-            };
-            println!("Went to state: {:?}", self.state);
+    fn update(&mut self, now: VirtualInstant) -> Vec<([i32; 2], ModuleState)> {
+        while let Some(duration) = self.phases[self.current_phase].duration {
+            let phase_end = self.phase_start + duration;
+            if now < phase_end || self.current_phase + 1 == self.phases.len() {
+                break;
+            }
+            // Pending repetitions end with their phase, reverts still happen
+            let ended_phase = self.current_phase;
+            self.armed_machine_state_changes
+                .retain(|change| change.phase != Some(ended_phase) || change.repeat.is_none());
+            self.current_phase += 1;
+            self.phase_start = phase_end;
+            self.arm_phase();
         }
         let mut activated_machine_states = Vec::new();
-        let mut machine_state_changes = std::mem::take(&mut self.machine_state_changes);
-        machine_state_changes.retain(|machine_state_change| {
-            let is_active = machine_state_change.time < time.now().inner();
-            if is_active {
-                activated_machine_states.push(machine_state_change.clone());
+        while let Some(i) = self
+            .armed_machine_state_changes
+            .iter()
+            .enumerate()
+            .filter(|(_, change)| change.at <= now)
+            .min_by_key(|(_, change)| change.at)
+            .map(|(i, _)| i)
+        {
+            let change = self.armed_machine_state_changes.swap_remove(i);
+            if let Some(duration) = change.duration {
+                self.armed_machine_state_changes.push(ArmedStateChange {
+                    at: change.at + duration,
+                    state: ModuleState::Functional,
+                    pos: change.pos,
+                    duration: None,
+                    repeat: None,
+                    phase: None,
+                });
             }
-            !is_active
-        });
-        self.machine_state_changes = machine_state_changes;
+            if let Some(repeat) = change.repeat
+                && change.phase == Some(self.current_phase)
+            {
+                self.armed_machine_state_changes.push(ArmedStateChange {
+                    at: change.at + repeat,
+                    ..change.clone()
+                });
+            }
+            activated_machine_states.push((change.pos, change.state));
+        }
         activated_machine_states
     }
 }
-#[derive(Debug, Clone, Copy)]
-pub enum ScenarioState {
-    Start,
-    Disturbtion,
-    End,
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ModuleSnapshot {
+    in_production: u32,
+    state: ModuleState,
+}
+
+/// Everything that changes while a scenario runs, to continue the run from there.
+/// The scenario itself is not part of it and is looked up by name on restore.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub scenario: String,
+    pub time: VirtualInstant,
+    phase: usize,
+    phase_start: VirtualInstant,
+    armed_machine_state_changes: Vec<ArmedStateChange>,
+    modules: [[ModuleSnapshot; X_NUM_MODULES]; Y_NUM_MODULES],
+    products: Vec<Product>,
+    queue: EventQueue,
+    next_product_id: u64,
+    /// Released, completed and late products of every order and when it was finished
+    orders: Vec<(u32, u32, u32, Option<VirtualInstant>)>,
+    breakdowns: Breakdowns,
+    rng: ChaCha8Rng,
+    metrics: Metrics,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        serde_json::from_str(&source).map_err(|err| format!("{}: {err}", path.display()))
+    }
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        std::fs::write(path, json)
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
 }
 
 pub struct Board {
@@ -277,6 +469,16 @@ pub struct Board {
     current_scenario: Scenario,
     pub time_manager: TimeManager,
     products: Vec<Product>,
+    pub metrics: Metrics,
+    pub order_book: OrderBook,
+    rng: ChaCha8Rng,
+    breakdowns: Breakdowns,
+    pub events: EventBus,
+    /// Id of the next product started in the running scenario
+    next_product_id: u64,
+    /// Virtual time the simulation reached, see [`Board::now`]
+    time: VirtualInstant,
+    queue: EventQueue,
 }
 
 impl Default for Board {
@@ -287,13 +489,69 @@ impl Default for Board {
 
 impl Board {
     pub fn set_scenario(&mut self, scenario: Scenario) {
-        println!("Starting Scenario: {}", &scenario.name);
+        if !self.time.inner().is_zero() {
+            self.emit(
+                EventKind::ScenarioStopped {
+                    scenario: self.current_scenario.name.clone(),
+                },
+                None,
+                None,
+            );
+        }
         for module in self.modules.as_flattened_mut() {
             module.reset();
         }
+        self.rng = ChaCha8Rng::seed_from_u64(scenario.seed);
+        self.breakdowns = Breakdowns::new(&scenario.reliability, &mut self.rng);
+        self.order_book = OrderBook::new(&scenario.orders);
         self.current_scenario = scenario;
         self.time_manager.reset();
+        self.time = VirtualInstant::zero();
+        self.queue = EventQueue::default();
         self.products = Vec::new();
+        self.next_product_id = 0;
+        self.metrics = Metrics::new();
+        let storages = self
+            .modules
+            .as_flattened()
+            .iter()
+            .filter(|module| module.is_storage())
+            .collect::<Vec<_>>();
+        let storage_capacity = storages
+            .iter()
+            .map(|module| module.max_production)
+            .max()
+            .unwrap_or(MAX_PRODUCT_IN_STORAGE);
+        let storages = storages.iter().map(|module| module.pos).collect();
+        self.emit(
+            EventKind::ScenarioStarted {
+                scenario: self.current_scenario.name.clone(),
+                storages,
+                storage_capacity,
+            },
+            None,
+            None,
+        );
+        self.emit_phase_changed();
+    }
+    pub fn scenario(&self) -> &Scenario {
+        &self.current_scenario
+    }
+    pub fn scenario_name(&self) -> &str {
+        &self.current_scenario.name
+    }
+    pub fn phase_name(&self) -> &str {
+        self.current_scenario.phase_name()
+    }
+    /// Random generator of the running scenario, seeded from the scenario
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.rng
+    }
+    pub fn layout(&self) -> &Layout {
+        &self.current_scenario.layout
+    }
+    pub fn scenario_progress(&self) -> f32 {
+        self.current_scenario.progress(self.time_manager.now())
     }
     pub fn colors(&self) -> Vec<Srgb> {
         let mut colors = Vec::new();
@@ -314,22 +572,37 @@ impl Board {
 
         colors
     }
-    pub fn set_storage(&mut self, product_plan: ProductPlan) {
+    /// Let the storage steps of the plan hold `capacity` products
+    pub fn set_storage(&mut self, product_plan: ProductPlan, capacity: u32) {
         for step in &product_plan.steps {
             if step.is_storage() {
-                self[step.maschine_pos()].max_production = MAX_PRODUCT_IN_STORAGE;
+                for machine in step.machines() {
+                    self[machine].storage = true;
+                    self[machine].max_production = capacity;
+                }
             }
         }
     }
-    pub fn inbounds(&self, pos: [i32; 2]) -> bool {
+    pub fn inbounds(pos: [i32; 2]) -> bool {
         pos[0] >= 0 && pos[1] >= 0 && pos[0] < X_NUM_MODULES as i32 && pos[1] < Y_NUM_MODULES as i32
     }
     pub fn new() -> Self {
+        Self::with_clock_source(ClockSource::RealTime)
+    }
+    pub fn with_clock_source(clock_source: ClockSource) -> Self {
         Self {
             modules: from_fn(|y| from_fn(|x| Module::new([x as i32, y as i32], LED_OFF_COLOR))),
-            time_manager: TimeManager::new(),
-            current_scenario: Scenario::starting_scenario(),
+            time_manager: TimeManager::with_clock_source(clock_source),
+            current_scenario: Scenario::idle(),
             products: Vec::new(),
+            metrics: Metrics::new(),
+            order_book: OrderBook::default(),
+            rng: ChaCha8Rng::seed_from_u64(0),
+            breakdowns: Breakdowns::default(),
+            events: EventBus::default(),
+            next_product_id: 0,
+            time: VirtualInstant::zero(),
+            queue: EventQueue::default(),
         }
     }
     pub fn iter_mut_leds(&mut self) -> impl Iterator<Item = ([f32; 2], &mut Srgb)> {
@@ -341,8 +614,8 @@ impl Board {
     #[cfg(target_arch = "x86_64")]
     pub fn set_screen_size() {
         request_new_screen_size(
-            X_NUM_MODULES as f32 * PIXEL_PER_MODULE,
-            Y_NUM_MODULES as f32 * PIXEL_PER_MODULE,
+            X_NUM_MODULES as f32 * PIXEL_PER_MODULE + DASHBOARD_WIDTH,
+            Y_NUM_MODULES as f32 * PIXEL_PER_MODULE + TIMELINE_HEIGHT,
         );
     }
     #[cfg(target_arch = "x86_64")]
@@ -356,6 +629,10 @@ impl Board {
             module.draw();
         }
     }
+    /// Show a status on an LED that no product path ever reaches
+    pub fn draw_status_pixel(&mut self, color: Srgb) {
+        self[STATUS_PIXEL_MODULE].brightness_y[0] = color;
+    }
     pub fn reset(&mut self, color: Srgb) {
         for module in self.modules.as_flattened_mut() {
             module.set_all_colors(color);
@@ -375,48 +652,373 @@ impl Board {
         }
     }
 
+    /// Simulate up to the current virtual time and draw the products on the LEDs
     pub fn update(&mut self) {
-        self.time_manager.update();
-        let activated_machine_states = self.current_scenario.update(&self.time_manager);
-        for machine_state in activated_machine_states {
-            println!("Activated Statechange at: {:?}", machine_state.pos);
-            println!(
-                "From {:?} to {:?}",
-                self[machine_state.pos].state, machine_state.state
-            );
-            self[machine_state.pos].state = machine_state.state;
+        self.advance();
+        self.draw_products();
+    }
+    /// Show every product where it is at the current time, parked products fill
+    /// their storage
+    fn draw_products(&mut self) {
+        let products = std::mem::take(&mut self.products);
+        for product in &products {
+            if let Some(pos) = product.parked_at() {
+                self[pos].in_storage += 1;
+            }
+            self.draw_light_point(product.light_point(self.time), product.color);
+        }
+        self.products = products;
+    }
+}
+
+impl Board {
+    /// Capture the running scenario, see [`Board::restore`]
+    pub fn snapshot(&self) -> Snapshot {
+        let scenario = &self.current_scenario;
+        Snapshot {
+            scenario: scenario.name.clone(),
+            time: self.time,
+            phase: scenario.current_phase,
+            phase_start: scenario.phase_start,
+            armed_machine_state_changes: scenario.armed_machine_state_changes.clone(),
+            modules: from_fn(|y| {
+                from_fn(|x| ModuleSnapshot {
+                    in_production: self.modules[y][x].in_production,
+                    state: self.modules[y][x].state.clone(),
+                })
+            }),
+            products: self.products.clone(),
+            queue: self.queue.clone(),
+            next_product_id: self.next_product_id,
+            orders: self
+                .order_book
+                .orders
+                .iter()
+                .map(|progress| {
+                    (
+                        progress.released,
+                        progress.completed,
+                        progress.late,
+                        progress.finished,
+                    )
+                })
+                .collect(),
+            breakdowns: self.breakdowns.clone(),
+            rng: self.rng.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+    /// Start `scenario` and continue it from the snapshot. The capacities of the
+    /// modules are kept, they belong to the board rather than the run.
+    pub fn restore(&mut self, scenario: Scenario, snapshot: Snapshot) -> Result<(), String> {
+        if scenario.name != snapshot.scenario {
+            return Err(format!(
+                "The snapshot is of scenario {}, not {}",
+                snapshot.scenario, scenario.name
+            ));
+        }
+        if snapshot.phase >= scenario.phases.len() || snapshot.orders.len() != scenario.orders.len()
+        {
+            return Err(format!(
+                "The snapshot does not fit the phases and orders of scenario {}",
+                scenario.name
+            ));
         }
+        self.set_scenario(scenario);
+        self.time_manager.set_now(snapshot.time);
+        self.time = snapshot.time;
 
-        let current_steps = self.current_scenario.current_steps();
+        let scenario = &mut self.current_scenario;
+        scenario.current_phase = snapshot.phase;
+        scenario.phase_start = snapshot.phase_start;
+        scenario.armed_machine_state_changes = snapshot.armed_machine_state_changes;
+        for (module, saved) in self
+            .modules
+            .as_flattened_mut()
+            .iter_mut()
+            .zip(snapshot.modules.into_iter().flatten())
+        {
+            module.in_production = saved.in_production;
+            module.state = saved.state;
+        }
+        self.products = snapshot.products;
+        self.queue = snapshot.queue;
+        self.next_product_id = snapshot.next_product_id;
+        for (progress, (released, completed, late, finished)) in
+            self.order_book.orders.iter_mut().zip(snapshot.orders)
+        {
+            progress.released = released;
+            progress.completed = completed;
+            progress.late = late;
+            progress.finished = finished;
+        }
+        self.breakdowns = snapshot.breakdowns;
+        self.rng = snapshot.rng;
+        self.metrics = snapshot.metrics;
+        self.emit_phase_changed();
+        Ok(())
+    }
+    /// Advance the virtual time and simulate up to it
+    pub fn advance(&mut self) {
+        self.time_manager.update();
+        self.simulate();
+    }
+    /// Virtual time the simulation reached, the time of the event being handled
+    pub fn now(&self) -> VirtualInstant {
+        self.time
+    }
+    pub fn schedule(&mut self, at: VirtualInstant, product: u64, event: ProductEvent) {
+        self.queue.schedule(at, product, event);
+    }
+    /// Handle everything that happens until the time of the time manager in order,
+    /// each at the time it is due. The result does not depend on how often this
+    /// is called, only on the virtual time reached.
+    fn simulate(&mut self) {
+        let end = self.time_manager.now();
+        loop {
+            self.release_and_dispatch();
+            let next = [
+                self.queue.next_time(),
+                self.current_scenario.next_change(),
+                self.breakdowns.next_change(),
+                self.order_book.next_release(self.time),
+            ]
+            .into_iter()
+            .flatten()
+            .min()
+            .filter(|next| *next <= end);
+            let Some(next) = next else {
+                break;
+            };
+            self.record_metrics(next);
+            self.time = next;
+            self.handle_due();
+        }
+        self.record_metrics(end);
+        self.time = end;
+    }
+    /// Phase ends, machine state changes and product events due at the current time
+    fn handle_due(&mut self) {
+        let phase = self.current_scenario.current_phase();
+        let state_changes = self.current_scenario.update(self.time);
+        if self.current_scenario.current_phase() != phase {
+            self.emit_phase_changed();
+        }
+        for (pos, state) in state_changes {
+            self.set_module_state(pos, state);
+        }
+        let modules = &self.modules;
+        let breakdowns = self.breakdowns.update(
+            self.time,
+            |pos| modules[pos[1] as usize][pos[0] as usize].state.clone(),
+            &mut self.rng,
+        );
+        for (pos, state) in breakdowns {
+            self.set_module_state(pos, state);
+        }
 
-        let mut new_products = Vec::new();
-        for product in current_steps {
-            let starting_maschine = product.steps[0].maschine_pos();
-            if self[starting_maschine].can_receiv_product() {
-                new_products.push((
-                    Product::new(product.color, product.steps, &self.time_manager),
-                    starting_maschine,
-                ))
+        while let Some((id, event)) = self.queue.pop_due(self.time) {
+            let Some(i) = self.products.iter().position(|product| product.id == id) else {
+                continue;
+            };
+            let mut product = self.products.remove(i);
+            let stays = match event {
+                ProductEvent::StepFinished => {
+                    product.finish_step(self);
+                    true
+                }
+                ProductEvent::Arrived => product.arrive(self),
+            };
+            if stays {
+                self.products.insert(i, product);
+            } else {
+                self.product_finished(&product);
             }
         }
-        for (product, starting_pos) in new_products {
-            self[starting_pos].in_production += 1;
-            self.products.push(product);
+    }
+    fn product_finished(&mut self, product: &Product) {
+        let pos = product.finish(self);
+        self.emit(EventKind::ProductFinished, Some(product.id), Some(pos));
+        if let Some(order) = product.order {
+            self.order_book.product_finished(order, self.time);
+        }
+        self.metrics.product_finished(
+            self.current_scenario.phase_name(),
+            self.time,
+            &product.plan,
+            (self.time - product.created).inner(),
+        );
+    }
+    /// Start products and move waiting ones on until neither is possible anymore
+    fn release_and_dispatch(&mut self) {
+        loop {
+            let released = self.release_products();
+            let mut products = std::mem::take(&mut self.products);
+            let dispatched = self.dispatch(&mut products);
+            self.products = products;
+            if !released && !dispatched {
+                break;
+            }
         }
+    }
+    /// Start a product of every open order and plan of the current phase whose first
+    /// machine is free, if the release policy allows it
+    fn release_products(&mut self) -> bool {
+        let orders = self
+            .order_book
+            .open(self.time)
+            .map(|(i, order)| (order.plan.clone(), Some((i, order.due))))
+            .collect::<Vec<_>>();
+        let current_steps = self
+            .current_scenario
+            .current_steps()
+            .into_iter()
+            .map(|product_plan| (product_plan, None));
 
-        let mut products = std::mem::take(&mut self.products);
-        products.retain_mut(|product: &mut Product| {
-            let Some(light_point_pos) = product.next(self) else {
-                product.finish(self);
-                return false;
+        let release_policy = self.current_scenario.release_policy;
+        let mut released = false;
+        for (product_plan, order) in orders.into_iter().chain(current_steps) {
+            let first_step = &product_plan.steps[0];
+            let starting_pos = first_step.maschine_pos();
+            let wip = self.products.len();
+            let at_first_step = at_step(
+                &self.products,
+                &product_plan.name,
+                product_plan.steps.len() - 1,
+            );
+            if !self[starting_pos].can_receiv_product()
+                || !release_policy.allows(wip, at_first_step)
+            {
+                continue;
+            }
+            let mut product = Product::new(
+                &product_plan,
+                self.next_product_id,
+                self.time,
+                &mut self.rng,
+            );
+            self.next_product_id += 1;
+            if let Some((order, due)) = order {
+                self.order_book.product_released(order);
+                product.order = Some(order);
+                // Without its own due date the order keeps the one of the plan
+                if let Some(due) = due {
+                    product.due = Some(VirtualInstant::zero() + due);
+                }
+            }
+            self[starting_pos].in_production += 1;
+            let id = Some(product.id);
+            let spawned = EventKind::ProductSpawned {
+                plan: product.plan.clone(),
+                color: [product.color.red, product.color.green, product.color.blue],
+                path: first_step.path().into(),
             };
-            self.draw_light_point(light_point_pos, product.color);
-            true
+            self.emit(spawned, id, Some(starting_pos));
+            self.emit(EventKind::StepStarted, id, Some(starting_pos));
+            self.metrics.product_started(
+                self.current_scenario.phase_name(),
+                self.time,
+                &product.plan,
+            );
+            if let Some(until) = product.step_end() {
+                self.queue
+                    .schedule(until, product.id, ProductEvent::StepFinished);
+            }
+            self.products.push(product);
+            released = true;
+        }
+        released
+    }
+    /// Hand free machines to the waiting products in the order of the dispatch rule.
+    /// After every dispatch the best ranked products get another chance, since the
+    /// product that left may have freed the machine they wait for.
+    fn dispatch(&mut self, products: &mut [Product]) -> bool {
+        let dispatch_rule = self.current_scenario.dispatch_rule;
+        let release_policy = self.current_scenario.release_policy;
+        let mut waiting = (0..products.len())
+            .filter(|i| products[*i].waiting_for_machine().is_some())
+            .collect::<Vec<_>>();
+        waiting.sort_by(|a, b| dispatch_rule.compare(&products[*a], &products[*b]));
+        let mut dispatched = false;
+        while let Some(i) = waiting.iter().position(|&product| {
+            let next_step = products[product].steps_left() - 1;
+            release_policy.allows_move(at_step(products, &products[product].plan, next_step))
+                && products[product].dispatch(self)
+        }) {
+            waiting.remove(i);
+            dispatched = true;
+        }
+        for i in waiting {
+            if let Some(pos) = products[i].report_blocked() {
+                self.emit(
+                    EventKind::WaitingForMachine,
+                    Some(products[i].id),
+                    Some(pos),
+                );
+            }
+        }
+        dispatched
+    }
+    pub fn set_module_state(&mut self, pos: [i32; 2], state: ModuleState) {
+        let previous = std::mem::replace(&mut self[pos].state, state.clone());
+        self.emit(
+            EventKind::MachineStateChanged { previous, state },
+            None,
+            Some(pos),
+        );
+    }
+    /// Publish an event at the current virtual time, if anyone listens
+    pub fn emit(&mut self, kind: EventKind, product: Option<u64>, pos: Option<[i32; 2]>) {
+        if !self.events.has_subscribers() {
+            return;
+        }
+        let event = Event {
+            time: self.time.inner().as_secs_f64(),
+            kind,
+            product,
+            pos,
+        };
+        self.events.publish(&event);
+    }
+    fn emit_phase_changed(&mut self) {
+        let phase = self.current_scenario.phase_name().to_string();
+        self.emit(EventKind::PhaseChanged { phase }, None, None);
+    }
+    /// Account the time from the current time until `until` to the metrics
+    fn record_metrics(&mut self, until: VirtualInstant) {
+        if until <= self.time {
+            return;
+        }
+        let mut processing = [[false; X_NUM_MODULES]; Y_NUM_MODULES];
+        let mut blocked = [[false; X_NUM_MODULES]; Y_NUM_MODULES];
+        for product in &self.products {
+            match product.activity() {
+                Activity::Processing(pos) => processing[pos[1] as usize][pos[0] as usize] = true,
+                Activity::Blocked(pos) => blocked[pos[1] as usize][pos[0] as usize] = true,
+                Activity::Moving => {}
+            }
+        }
+        let utilization = from_fn(|y| {
+            from_fn(|x| metrics::classify(&self.modules[y][x], processing[y][x], blocked[y][x]))
         });
-        self.products = products;
+        self.metrics.record(
+            self.current_scenario.phase_name(),
+            self.time,
+            (until - self.time).inner(),
+            self.products.len(),
+            &utilization,
+        );
     }
 }
 
+/// Products of `plan` at or moving to the step with `steps_left` steps after it
+fn at_step(products: &[Product], plan: &str, steps_left: usize) -> usize {
+    products
+        .iter()
+        .filter(|product| product.plan == plan && product.steps_left() == steps_left)
+        .count()
+}
+
 impl Index<[i32; 2]> for Board {
     type Output = Module;
 
@@ -0,0 +1,230 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    batch::BatchConfig,
+    constants::{DEFAULT_DEVICE_FILE, DEFAULT_SCENARIO_FILE, MAX_PRODUCT_IN_STORAGE},
+    headless::HeadlessConfig,
+    sweep::SweepConfig,
+};
+
+const USAGE: &str = "\
+Usage: demonstrator [options] [command]
+
+Commands:
+  run                    Show the board on screen or the LEDs (default)
+  headless [run options] Simulate a scenario as fast as possible without output
+  batch [run options]    Repeat a headless run with different seeds and summarize the KPIs
+  sweep [run options]    Batch every combination of capacities and step times
+  replay <file>          Play back an event log written with --events, at any speed
+  validate               Check the scenario file and print every problem found
+
+Options:
+  --scenarios <file>     Scenario file (default: scenarios.toml)
+  --devices <file>       Button role config (default: devices.toml)
+  --list-devices         Print the serial devices and their roles, then exit
+  --events <file>        Write every board event as a JSON line, `-` for stdout (run and headless)
+  --restore <file>       Continue the run of a snapshot instead of starting a scenario (run and headless)
+  --snapshot <file>      Where to save a snapshot, at the end of a headless run or with S on
+                         screen (default for S: snapshot.json)
+
+Headless options:
+  --scenario <n>         Scenario to run, counted from 1 (default: 1)
+  --duration <seconds>   Virtual time to simulate (default: 120)
+  --dt <seconds>         Virtual time per update (default: 0.01)
+  --seed <n>             Random seed, overrides the seed of the scenario
+
+Batch options:
+  --runs <n>             Number of runs, seeds count up from the seed (default: 10)
+  --csv <file>           Write the runs and the summary as CSV
+  --json <file>          Write the runs and the summary as JSON
+
+Sweep options (lists are comma separated, batch options apply, --runs defaults to 1):
+  --storage <list>       Products a storage holds (default: 5)
+  --capacity <x,y=list>  Products the module at x,y works on at once, repeatable
+  --time-scale <list>    Factors on the production time of every step (default: 1)";
+
+pub enum Mode {
+    Run,
+    ListDevices,
+    Headless(HeadlessConfig),
+    Batch(BatchConfig),
+    Sweep(SweepConfig),
+    /// Event log to play back
+    Replay(PathBuf),
+    Validate,
+}
+
+pub struct Args {
+    pub scenario_file: PathBuf,
+    pub device_file: PathBuf,
+    /// Where to write the event log, `-` for stdout
+    pub event_log: Option<PathBuf>,
+    /// Snapshot to continue from
+    pub restore: Option<PathBuf>,
+    /// Where to save a snapshot
+    pub snapshot: Option<PathBuf>,
+    pub mode: Mode,
+}
+
+impl Args {
+    /// Parse the command line, exiting with a usage message on invalid input
+    pub fn parse() -> Self {
+        let mut args = Self {
+            scenario_file: PathBuf::from(DEFAULT_SCENARIO_FILE),
+            device_file: PathBuf::from(DEFAULT_DEVICE_FILE),
+            event_log: None,
+            restore: None,
+            snapshot: None,
+            mode: Mode::Run,
+        };
+        let mut headless = HeadlessConfig {
+            scenario: 1,
+            duration: Duration::from_secs(120),
+            time_step: Duration::from_millis(10),
+            seed: None,
+            storage_capacity: MAX_PRODUCT_IN_STORAGE,
+            module_capacities: Vec::new(),
+            time_scale: 1.0,
+        };
+        let mut runs = None;
+        let mut storage_capacities = Vec::new();
+        let mut module_capacities = Vec::new();
+        let mut time_scales = Vec::new();
+        let mut csv = None;
+        let mut json = None;
+        let mut command = None;
+        let mut replay = None;
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--scenarios" => args.scenario_file = PathBuf::from(expect_value(&mut iter, &arg)),
+                "--devices" => args.device_file = PathBuf::from(expect_value(&mut iter, &arg)),
+                "--list-devices" => args.mode = Mode::ListDevices,
+                "--events" => args.event_log = Some(PathBuf::from(expect_value(&mut iter, &arg))),
+                "--restore" => args.restore = Some(PathBuf::from(expect_value(&mut iter, &arg))),
+                "--snapshot" => args.snapshot = Some(PathBuf::from(expect_value(&mut iter, &arg))),
+                "--scenario" => headless.scenario = parse_value(&mut iter, &arg),
+                "--duration" => headless.duration = parse_seconds(&mut iter, &arg),
+                "--dt" => headless.time_step = parse_seconds(&mut iter, &arg),
+                "--seed" => headless.seed = Some(parse_value(&mut iter, &arg)),
+                "--runs" => runs = Some(parse_positive(&mut iter, &arg)),
+                "--storage" => storage_capacities = parse_positive_list(&mut iter, &arg),
+                "--capacity" => {
+                    let value = expect_value(&mut iter, &arg);
+                    let capacity = value
+                        .split_once('=')
+                        .and_then(|(pos, capacities)| {
+                            let (x, y) = pos.split_once(',')?;
+                            let pos = [x.trim().parse().ok()?, y.trim().parse().ok()?];
+                            Some((pos, parse_list(capacities)?))
+                        })
+                        .filter(|(_, capacities): &(_, Vec<u32>)| !capacities.contains(&0))
+                        .unwrap_or_else(|| {
+                            exit_with_usage(&format!("invalid value `{value}` for `{arg}`"))
+                        });
+                    module_capacities.push(capacity);
+                }
+                "--time-scale" => time_scales = parse_positive_list(&mut iter, &arg),
+                "--csv" => csv = Some(PathBuf::from(expect_value(&mut iter, &arg))),
+                "--json" => json = Some(PathBuf::from(expect_value(&mut iter, &arg))),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                "run" | "headless" | "batch" | "sweep" | "validate" if command.is_none() => {
+                    command = Some(arg)
+                }
+                "replay" if command.is_none() => {
+                    replay = Some(PathBuf::from(expect_value(&mut iter, &arg)));
+                    command = Some(arg);
+                }
+                _ => exit_with_usage(&format!("unknown argument `{arg}`")),
+            }
+        }
+        if matches!(args.mode, Mode::Run) {
+            if headless.time_step.is_zero() {
+                exit_with_usage("`--dt` must be greater than zero");
+            }
+            match command.as_deref() {
+                Some("headless") => args.mode = Mode::Headless(headless),
+                Some("batch") => {
+                    args.mode = Mode::Batch(BatchConfig {
+                        run: headless,
+                        runs: runs.unwrap_or(10),
+                        csv,
+                        json,
+                    })
+                }
+                Some("sweep") => {
+                    args.mode = Mode::Sweep(SweepConfig {
+                        run: headless,
+                        runs: runs.unwrap_or(1),
+                        storage_capacities,
+                        module_capacities,
+                        time_scales,
+                        csv,
+                        json,
+                    })
+                }
+                Some("replay") => args.mode = Mode::Replay(replay.expect("Set with the command")),
+                Some("validate") => args.mode = Mode::Validate,
+                _ => {}
+            }
+        }
+        args
+    }
+}
+
+fn expect_value(iter: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    iter.next()
+        .unwrap_or_else(|| exit_with_usage(&format!("`{flag}` needs a value")))
+}
+
+fn parse_value<T: std::str::FromStr>(iter: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    let value = expect_value(iter, flag);
+    value
+        .parse()
+        .unwrap_or_else(|_| exit_with_usage(&format!("invalid value `{value}` for `{flag}`")))
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(
+    iter: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> T {
+    let value = expect_value(iter, flag);
+    value
+        .parse()
+        .ok()
+        .filter(|parsed| *parsed > T::default())
+        .unwrap_or_else(|| exit_with_usage(&format!("invalid value `{value}` for `{flag}`")))
+}
+
+fn parse_seconds(iter: &mut impl Iterator<Item = String>, flag: &str) -> Duration {
+    let seconds: f64 = parse_value(iter, flag);
+    Duration::try_from_secs_f64(seconds)
+        .unwrap_or_else(|_| exit_with_usage(&format!("invalid duration `{seconds}` for `{flag}`")))
+}
+
+/// Comma separated values, `None` if one of them does not parse
+fn parse_list<T: std::str::FromStr>(value: &str) -> Option<Vec<T>> {
+    value
+        .split(',')
+        .map(|item| item.trim().parse().ok())
+        .collect()
+}
+
+fn parse_positive_list<T: std::str::FromStr + PartialOrd + Default>(
+    iter: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Vec<T> {
+    let value = expect_value(iter, flag);
+    parse_list(&value)
+        .filter(|values: &Vec<T>| values.iter().all(|value| *value > T::default()))
+        .unwrap_or_else(|| exit_with_usage(&format!("invalid value `{value}` for `{flag}`")))
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
@@ -0,0 +1,433 @@
+use std::{
+    fmt::{self, Display},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use palette::Srgb;
+use serialport::{SerialPort, SerialPortInfo};
+
+use crate::{
+    board::{Board, Scenario},
+    constants::{BAUD_RATE, GREEN, RED, YELLOW},
+    devices::{self, DeviceConfig, DeviceRole},
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+/// Progress is reported to the buttons in steps of this size
+const PROGRESS_STEP: f32 = 0.1;
+
+/// Command sent by one of the ESP32 buttons, one per line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Boot,
+    /// Select a scenario, counted from 1 like the labels on the button
+    Scenario(usize),
+    Start,
+    Pause,
+    Resume,
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCommandError(String);
+
+impl Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid command `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseCommandError {}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let lowercase = line.trim().to_ascii_lowercase();
+        let words = lowercase.split_ascii_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            ["boot"] => Ok(Command::Boot),
+            ["scenario", number] => match number.parse() {
+                Ok(number) if number >= 1 => Ok(Command::Scenario(number)),
+                _ => Err(ParseCommandError(line.trim().to_string())),
+            },
+            ["start"] => Ok(Command::Start),
+            ["pause"] => Ok(Command::Pause),
+            ["resume"] => Ok(Command::Resume),
+            ["stop"] => Ok(Command::Stop),
+            _ => Err(ParseCommandError(line.trim().to_string())),
+        }
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Boot => write!(f, "boot"),
+            Command::Scenario(number) => write!(f, "scenario {number}"),
+            Command::Start => write!(f, "start"),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+/// Message sent back to the buttons
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    /// Acknowledges an executed command
+    Ack(Command),
+    /// Fraction of the running scenario that is done, from 0.0 to 1.0
+    Progress(f32),
+    Error,
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ack(command) => write!(f, "{command}"),
+            Response::Progress(progress) => write!(f, "progress {progress:.1}"),
+            Response::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Connection of one button role, as shown on screen and on the status pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+/// Port to write to a button, with the flag that stops its reader thread
+struct Writer {
+    role: DeviceRole,
+    port: Box<dyn SerialPort>,
+    closed: Arc<AtomicBool>,
+}
+
+enum ControlEvent {
+    Connected(Writer),
+    Line(DeviceRole, String),
+    Disconnected(DeviceRole),
+}
+
+/// Serial connection to the buttons, each port is read on its own thread.
+/// Lost buttons are searched for again in the background until they are back.
+pub struct SerialControl {
+    writers: Vec<Writer>,
+    events: Receiver<ControlEvent>,
+}
+
+impl SerialControl {
+    pub fn start(config: DeviceConfig) -> Self {
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || supervise(config, sender));
+        Self {
+            writers: Vec::new(),
+            events,
+        }
+    }
+
+    /// Commands received since the last call, never blocks
+    pub fn poll(&mut self) -> Vec<(DeviceRole, Command)> {
+        let mut commands = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                ControlEvent::Connected(writer) => {
+                    eprintln!("Connected {}", writer.role);
+                    self.writers.push(writer);
+                }
+                ControlEvent::Disconnected(role) => {
+                    eprintln!("Lost {role}");
+                    self.writers.retain(|writer| writer.role != role);
+                }
+                ControlEvent::Line(role, line) => match line.parse() {
+                    Ok(command) => commands.push((role, command)),
+                    Err(err) => {
+                        eprintln!("{role}: {err}");
+                        self.send(role, Response::Error);
+                    }
+                },
+            }
+        }
+        commands
+    }
+
+    /// A button that can not be written to is closed, its reader thread then
+    /// reports it as lost and it is searched for again
+    pub fn send(&mut self, role: DeviceRole, response: Response) {
+        self.writers.retain_mut(|writer| {
+            if writer.role != role {
+                return true;
+            }
+            match writeln!(writer.port, "{response}") {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("Failed to write to {role}: {err}");
+                    writer.closed.store(true, Ordering::Relaxed);
+                    false
+                }
+            }
+        });
+    }
+
+    pub fn connection_state(&self, role: DeviceRole) -> ConnectionState {
+        if self.writers.iter().any(|writer| writer.role == role) {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        }
+    }
+}
+
+/// Keep looking for missing buttons, waiting longer after every failed attempt
+fn supervise(config: DeviceConfig, events: Sender<ControlEvent>) {
+    let (lost_sender, lost) = mpsc::channel();
+    let mut connected: Vec<(DeviceRole, String)> = Vec::new();
+    let mut backoff = MIN_RECONNECT_BACKOFF;
+    loop {
+        for role in lost.try_iter() {
+            connected.retain(|(connected_role, _)| *connected_role != role);
+            backoff = MIN_RECONNECT_BACKOFF;
+        }
+        let missing = DeviceRole::ALL
+            .into_iter()
+            .filter(|role| {
+                !connected
+                    .iter()
+                    .any(|(connected_role, _)| connected_role == role)
+            })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            // Sleep until a reader thread reports a lost button
+            let Ok(role) = lost.recv() else {
+                return;
+            };
+            connected.retain(|(connected_role, _)| *connected_role != role);
+            continue;
+        }
+
+        let in_use = connected
+            .iter()
+            .map(|(_, port_name)| port_name.clone())
+            .collect::<Vec<_>>();
+        for (role, device) in devices::buttons(&devices::discover(&config, &in_use)) {
+            if !missing.contains(&role) || connected.iter().any(|(found, _)| *found == role) {
+                continue;
+            }
+            match open_port(role, &device, events.clone(), lost_sender.clone()) {
+                Ok(writer) => {
+                    if events.send(ControlEvent::Connected(writer)).is_err() {
+                        return;
+                    }
+                    connected.push((role, device.port_name));
+                }
+                Err(err) => eprintln!("Failed to open {role} on {}: {err}", device.port_name),
+            }
+        }
+
+        if connected.len() < DeviceRole::ALL.len() {
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+}
+
+fn open_port(
+    role: DeviceRole,
+    device: &SerialPortInfo,
+    events: Sender<ControlEvent>,
+    lost: Sender<DeviceRole>,
+) -> serialport::Result<Writer> {
+    let port = serialport::new(&device.port_name, BAUD_RATE)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    let reader = port.try_clone()?;
+    let closed = Arc::new(AtomicBool::new(false));
+    let reader_closed = closed.clone();
+    thread::spawn(move || {
+        read_lines(role, reader, &events, &reader_closed);
+        // The main loop has to know first, so it never sees the reconnect before the loss
+        let _ = events.send(ControlEvent::Disconnected(role));
+        let _ = lost.send(role);
+    });
+    Ok(Writer { role, port, closed })
+}
+
+/// Read until the port fails or `closed` is set, checked at least every `READ_TIMEOUT`
+fn read_lines(
+    role: DeviceRole,
+    port: Box<dyn SerialPort>,
+    events: &Sender<ControlEvent>,
+    closed: &AtomicBool,
+) {
+    let mut reader = BufReader::new(port);
+    let mut line = String::new();
+    loop {
+        if closed.load(Ordering::Relaxed) {
+            return;
+        }
+        match reader.read_line(&mut line) {
+            Ok(0) => return,
+            // A partial line stays in the buffer until the newline arrives
+            Ok(_) if !line.ends_with('\n') => {}
+            Ok(_) => {
+                if events
+                    .send(ControlEvent::Line(role, line.trim().to_string()))
+                    .is_err()
+                {
+                    return;
+                }
+                line.clear();
+            }
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            Err(err) => {
+                eprintln!("Stopped reading from {role}: {err}");
+                return;
+            }
+        }
+    }
+}
+
+/// Executes the button commands on the board and reports back to the buttons
+pub struct Controller {
+    serial: SerialControl,
+    selected_scenario: usize,
+    reported_progress: Option<f32>,
+}
+
+impl Controller {
+    pub fn new(serial: SerialControl) -> Self {
+        Self {
+            serial,
+            selected_scenario: 0,
+            reported_progress: None,
+        }
+    }
+
+    pub fn update(&mut self, board: &mut Board, scenarios: &[Scenario]) {
+        for (role, command) in self.serial.poll() {
+            eprintln!("{role}: {command}");
+            self.execute(command, board, scenarios);
+        }
+        self.report_progress(board);
+    }
+
+    pub fn connection_state(&self, role: DeviceRole) -> ConnectionState {
+        self.serial.connection_state(role)
+    }
+
+    /// Status pixel color: green if all buttons are connected, yellow if some, red if none
+    pub fn status_color(&self) -> Srgb {
+        let connected = DeviceRole::ALL
+            .into_iter()
+            .filter(|role| self.connection_state(*role) == ConnectionState::Connected)
+            .count();
+        match connected {
+            0 => RED,
+            count if count == DeviceRole::ALL.len() => GREEN,
+            _ => YELLOW,
+        }
+    }
+
+    fn execute(&mut self, command: Command, board: &mut Board, scenarios: &[Scenario]) {
+        match command {
+            Command::Boot => {}
+            Command::Scenario(number) => {
+                let Some(scenario) = scenarios.get(number - 1) else {
+                    eprintln!("Invalid scenario number {number}");
+                    self.serial
+                        .send(DeviceRole::ScenarioButton, Response::Error);
+                    return;
+                };
+                self.selected_scenario = number - 1;
+                self.reported_progress = None;
+                board.set_scenario(scenario.clone());
+            }
+            Command::Start => {
+                if let Some(scenario) = scenarios.get(self.selected_scenario) {
+                    board.set_scenario(scenario.clone());
+                }
+                board.time_manager.resume();
+                self.reported_progress = Some(-PROGRESS_STEP);
+            }
+            Command::Pause => board.time_manager.pause(),
+            Command::Resume => board.time_manager.resume(),
+            Command::Stop => {
+                self.reported_progress = None;
+                self.selected_scenario = 0;
+                if let Some(scenario) = scenarios.first() {
+                    board.set_scenario(scenario.clone());
+                }
+            }
+        }
+        self.serial
+            .send(DeviceRole::SpeedButton, Response::Ack(command));
+    }
+
+    /// Send the scenario progress after `start` whenever it passed the next step
+    fn report_progress(&mut self, board: &Board) {
+        let Some(reported) = self.reported_progress else {
+            return;
+        };
+        let progress = board.scenario_progress();
+        let stepped = (progress / PROGRESS_STEP).floor() * PROGRESS_STEP;
+        if stepped > reported {
+            self.serial
+                .send(DeviceRole::SpeedButton, Response::Progress(stepped));
+            self.reported_progress = (progress < 1.0).then_some(stepped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_in_any_case() {
+        assert_eq!("boot".parse(), Ok(Command::Boot));
+        assert_eq!(" Scenario 2\r".parse(), Ok(Command::Scenario(2)));
+        assert_eq!("START".parse(), Ok(Command::Start));
+        assert_eq!("pause".parse(), Ok(Command::Pause));
+        assert_eq!("resume".parse(), Ok(Command::Resume));
+        assert_eq!("stop".parse(), Ok(Command::Stop));
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        for line in [
+            "",
+            "scenario",
+            "scenario 0",
+            "scenario x",
+            "start now",
+            "jump",
+        ] {
+            assert_eq!(
+                line.parse::<Command>(),
+                Err(ParseCommandError(line.to_string())),
+                "{line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn commands_are_acknowledged_as_received() {
+        for command in [Command::Boot, Command::Scenario(3), Command::Stop] {
+            assert_eq!(Response::Ack(command).to_string().parse(), Ok(command));
+        }
+    }
+}
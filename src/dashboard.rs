@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use macroquad::prelude::*;
+
+use crate::{
+    board::Board,
+    constants::{DASHBOARD_WIDTH, PIXEL_PER_MODULE, X_NUM_MODULES, Y_NUM_MODULES},
+    metrics::{Metrics, ModuleTimes},
+};
+
+/// Window over which the throughput chart averages completions
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(20);
+const MARGIN: f32 = 10.0;
+const CHART_HEIGHT: f32 = 90.0;
+const PANEL_BACKGROUND: Color = Color::new(0.15, 0.15, 0.15, 1.0);
+
+/// Side panel next to the board with the live KPIs of the running scenario
+pub fn draw_dashboard(board: &Board) {
+    let metrics = &board.metrics;
+    let left = X_NUM_MODULES as f32 * PIXEL_PER_MODULE;
+    let height = Y_NUM_MODULES as f32 * PIXEL_PER_MODULE;
+    draw_rectangle(left, 0.0, DASHBOARD_WIDTH, height, PANEL_BACKGROUND);
+
+    let x = left + MARGIN;
+    let width = DASHBOARD_WIDTH - 2.0 * MARGIN;
+
+    let current = metrics.phases.last();
+    let throughput_series = metrics.throughput_series(THROUGHPUT_WINDOW);
+    let throughput = throughput_series
+        .last()
+        .map_or(0.0, |(_, throughput)| *throughput);
+    let lead_time = current
+        .and_then(|phase| phase.average_lead_time())
+        .map_or("-".to_string(), |lead_time| {
+            format!("{:.1}s", lead_time.as_secs_f32())
+        });
+    let orders = &board.order_book.orders;
+    let order_status = if orders.is_empty() {
+        String::new()
+    } else {
+        let completed = orders.iter().map(|order| order.completed).sum::<u32>();
+        let quantity = orders.iter().map(|order| order.order.quantity).sum::<u32>();
+        let late = orders
+            .iter()
+            .filter(|order| {
+                order
+                    .tardiness(board.now())
+                    .is_some_and(|tardiness| !tardiness.is_zero())
+            })
+            .count();
+        format!("   Orders {completed}/{quantity}, {late} late")
+    };
+    draw_text(
+        &format!(
+            "{}: {}{order_status}",
+            board.scenario_name(),
+            board.phase_name()
+        ),
+        x,
+        20.0,
+        20.0,
+        WHITE,
+    );
+    draw_text(
+        &format!(
+            "Throughput {throughput:.1}/min   WIP {}   Lead time {lead_time}",
+            metrics.current_wip
+        ),
+        x,
+        38.0,
+        16.0,
+        LIGHTGRAY,
+    );
+
+    let throughput_chart = Rect::new(x, 50.0, width, CHART_HEIGHT);
+    draw_chart(
+        metrics,
+        throughput_chart,
+        "Throughput / min",
+        &throughput_series
+            .iter()
+            .map(|(time, throughput)| (time.inner().as_secs_f32(), *throughput))
+            .collect::<Vec<_>>(),
+        SKYBLUE,
+    );
+
+    let wip_chart = Rect::new(x, 160.0, width, CHART_HEIGHT);
+    draw_chart(
+        metrics,
+        wip_chart,
+        "Work in progress",
+        &metrics
+            .samples
+            .iter()
+            .map(|sample| (sample.time.inner().as_secs_f32(), sample.wip as f32))
+            .collect::<Vec<_>>(),
+        ORANGE,
+    );
+
+    if let Some(phase) = current {
+        draw_utilization(
+            &phase.modules,
+            Rect::new(x, 270.0, width, height - 270.0 - MARGIN),
+        );
+    }
+}
+
+/// Line chart over the scenario time with the phase starts marked on the time axis
+fn draw_chart(metrics: &Metrics, area: Rect, title: &str, points: &[(f32, f32)], color: Color) {
+    draw_rectangle_lines(area.x, area.y, area.w, area.h, 1.0, GRAY);
+    draw_text(title, area.x + 4.0, area.y + 14.0, 16.0, LIGHTGRAY);
+
+    let end_time = points.last().map_or(1.0, |(time, _)| time.max(1.0));
+    let max_value = points
+        .iter()
+        .map(|(_, value)| *value)
+        .fold(1.0_f32, f32::max);
+    let to_screen = |time: f32, value: f32| {
+        vec2(
+            area.x + time / end_time * area.w,
+            area.y + area.h - value / max_value * (area.h - 20.0),
+        )
+    };
+
+    for phase in metrics.phases.iter().skip(1) {
+        let start = to_screen(phase.start.inner().as_secs_f32(), 0.0);
+        draw_line(start.x, area.y, start.x, area.y + area.h, 1.0, YELLOW);
+        draw_text(
+            &phase.phase,
+            start.x + 2.0,
+            area.y + area.h - 4.0,
+            14.0,
+            YELLOW,
+        );
+    }
+
+    for pair in points.windows(2) {
+        let from = to_screen(pair[0].0, pair[0].1);
+        let to = to_screen(pair[1].0, pair[1].1);
+        draw_line(from.x, from.y, to.x, to.y, 2.0, color);
+    }
+    draw_text(
+        &format!("{max_value:.0}"),
+        area.x + area.w - 30.0,
+        area.y + 14.0,
+        14.0,
+        GRAY,
+    );
+}
+
+/// One stacked bar per module that did any work: busy, blocked, starved, down
+fn draw_utilization(modules: &[[ModuleTimes; X_NUM_MODULES]; Y_NUM_MODULES], area: Rect) {
+    draw_text("Utilization", area.x, area.y + 12.0, 16.0, LIGHTGRAY);
+    let active = modules
+        .as_flattened()
+        .iter()
+        .enumerate()
+        .filter(|(_, times)| !(times.busy + times.blocked + times.down).is_zero())
+        .collect::<Vec<_>>();
+    if active.is_empty() {
+        return;
+    }
+
+    let bar_area = Rect::new(area.x, area.y + 20.0, area.w, area.h - 34.0);
+    let bar_width = bar_area.w / active.len() as f32;
+    for (slot, (i, times)) in active.iter().enumerate() {
+        let total = times.total().as_secs_f32().max(f32::EPSILON);
+        let x = bar_area.x + slot as f32 * bar_width;
+        let mut y = bar_area.y + bar_area.h;
+        for (part, color) in [
+            (times.busy, GREEN),
+            (times.blocked, YELLOW),
+            (times.starved, DARKGRAY),
+            (times.down, RED),
+        ] {
+            let height = part.as_secs_f32() / total * bar_area.h;
+            y -= height;
+            draw_rectangle(x + 1.0, y, bar_width - 2.0, height, color);
+        }
+        let label = format!("{},{}", i % X_NUM_MODULES, i / X_NUM_MODULES);
+        draw_text(
+            &label,
+            x + 2.0,
+            bar_area.y + bar_area.h + 12.0,
+            12.0,
+            LIGHTGRAY,
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fmt::{self, Display},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::constants::BAUD_RATE;
+
+/// How long a button gets to answer the `identify` handshake
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+const IDENTIFY_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceRole {
+    ScenarioButton,
+    SpeedButton,
+}
+
+impl DeviceRole {
+    pub const ALL: [DeviceRole; 2] = [DeviceRole::ScenarioButton, DeviceRole::SpeedButton];
+}
+
+impl Display for DeviceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceRole::ScenarioButton => write!(f, "scenario button"),
+            DeviceRole::SpeedButton => write!(f, "speed button"),
+        }
+    }
+}
+
+impl FromStr for DeviceRole {
+    type Err = ();
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "scenario_button" => Ok(DeviceRole::ScenarioButton),
+            "speed_button" => Ok(DeviceRole::SpeedButton),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Assignment of button roles, loaded from `devices.toml`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// USB vendor ID of the buttons
+    #[serde(default = "default_vid")]
+    pub vid: u16,
+    /// USB product ID of the buttons
+    #[serde(default = "default_pid")]
+    pub pid: u16,
+    /// Ask unknown buttons for their role over the serial line
+    #[serde(default = "default_identify")]
+    pub identify: bool,
+    #[serde(default)]
+    pub devices: Vec<DeviceEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceEntry {
+    pub role: DeviceRole,
+    pub serial_number: String,
+}
+
+fn default_vid() -> u16 {
+    0x303A
+}
+fn default_pid() -> u16 {
+    0x1001
+}
+fn default_identify() -> bool {
+    true
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            vid: default_vid(),
+            pid: default_pid(),
+            identify: default_identify(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// Load the config, falling back to the handshake only if the file is missing or invalid
+    pub fn load(path: &Path) -> Self {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("No device config at {}: {err}", path.display());
+                return Self::default();
+            }
+        };
+        match toml::from_str(&source) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Invalid device config {}: {err}", path.display());
+                Self::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    Config,
+    Handshake,
+}
+
+/// A serial port found on the system and the role it was given, if any
+pub struct Device {
+    pub port: SerialPortInfo,
+    pub role: Option<(DeviceRole, Assignment)>,
+}
+
+/// List all serial ports and assign button roles by config or handshake.
+/// Ports in `in_use` are already open and are not probed with the handshake.
+pub fn discover(config: &DeviceConfig, in_use: &[String]) -> Vec<Device> {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            eprintln!("Failed to list serial ports: {err}");
+            return Vec::new();
+        }
+    };
+    ports
+        .into_iter()
+        .filter(|port| !in_use.contains(&port.port_name))
+        .map(|port| {
+            let role = assign_role(config, &port);
+            Device { port, role }
+        })
+        .collect()
+}
+
+fn assign_role(config: &DeviceConfig, port: &SerialPortInfo) -> Option<(DeviceRole, Assignment)> {
+    let SerialPortType::UsbPort(ref usb_port_info) = port.port_type else {
+        // Skip non-USB ports
+        return None;
+    };
+    if usb_port_info.vid != config.vid || usb_port_info.pid != config.pid {
+        return None;
+    }
+    let configured = config
+        .devices
+        .iter()
+        .find(|entry| usb_port_info.serial_number.as_deref() == Some(entry.serial_number.as_str()));
+    if let Some(entry) = configured {
+        return Some((entry.role, Assignment::Config));
+    }
+    if !config.identify {
+        return None;
+    }
+    identify(&port.port_name).map(|role| (role, Assignment::Handshake))
+}
+
+/// Send `identify` and wait for a `role <name>` answer
+fn identify(port_name: &str) -> Option<DeviceRole> {
+    let port = serialport::new(port_name, BAUD_RATE)
+        .timeout(IDENTIFY_READ_TIMEOUT)
+        .open()
+        .ok()?;
+    let mut writer = port.try_clone().ok()?;
+    writeln!(writer, "identify").ok()?;
+
+    let deadline = Instant::now() + IDENTIFY_TIMEOUT;
+    let mut reader = BufReader::new(port);
+    let mut line = String::new();
+    while Instant::now() < deadline {
+        match reader.read_line(&mut line) {
+            Ok(0) => return None,
+            Ok(_) if !line.ends_with('\n') => {}
+            Ok(_) => {
+                if let ["role", role] = line.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+                    return role.parse().ok();
+                }
+                // Ignore anything else the button prints while booting
+                line.clear();
+            }
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// The devices that got a role, warning about duplicate roles
+pub fn buttons(devices: &[Device]) -> Vec<(DeviceRole, SerialPortInfo)> {
+    let buttons = devices
+        .iter()
+        .filter_map(|device| Some((device.role?.0, device.port.clone())))
+        .collect::<Vec<_>>();
+    for role in DeviceRole::ALL {
+        let count = buttons.iter().filter(|(found, _)| *found == role).count();
+        if count > 1 {
+            eprintln!("Found {count} devices with the role {role}, using the first");
+        }
+    }
+    buttons
+}
+
+/// Print every serial port and the role it got, used by `--list-devices`
+pub fn print_devices(devices: &[Device]) {
+    if devices.is_empty() {
+        println!("No serial ports found");
+    }
+    for device in devices {
+        let description = match &device.port.port_type {
+            SerialPortType::UsbPort(usb) => format!(
+                "USB {:04x}:{:04x} serial {}",
+                usb.vid,
+                usb.pid,
+                usb.serial_number.as_deref().unwrap_or("-")
+            ),
+            SerialPortType::PciPort => "PCI".to_string(),
+            SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+            SerialPortType::Unknown => "unknown".to_string(),
+        };
+        let role = match device.role {
+            Some((role, Assignment::Config)) => format!("{role} (config)"),
+            Some((role, Assignment::Handshake)) => format!("{role} (handshake)"),
+            None => "no role".to_string(),
+        };
+        println!("{}: {description}: {role}", device.port.port_name);
+    }
+}
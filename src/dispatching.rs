@@ -0,0 +1,41 @@
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+use crate::product::Product;
+
+/// Decides which of the products waiting for a machine gets it first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchRule {
+    /// Longest waiting product first
+    #[default]
+    Fifo,
+    /// Product with the shortest processing time at the next machine first
+    ShortestProcessingTime,
+    /// Product that is due first, products without a due date last
+    EarliestDueDate,
+    /// Product of the plan with the highest priority first
+    Priority,
+}
+
+impl DispatchRule {
+    /// Order in which products waiting for a machine may take a free one
+    pub fn compare(&self, a: &Product, b: &Product) -> Ordering {
+        let (Some((a_since, a_time)), Some((b_since, b_time))) =
+            (a.waiting_for_machine(), b.waiting_for_machine())
+        else {
+            return Ordering::Equal;
+        };
+        let by_rule = match self {
+            DispatchRule::Fifo => Ordering::Equal,
+            DispatchRule::ShortestProcessingTime => a_time.cmp(&b_time),
+            DispatchRule::EarliestDueDate => match (a.due, b.due) {
+                (Some(a_due), Some(b_due)) => a_due.cmp(&b_due),
+                (a_due, b_due) => b_due.is_some().cmp(&a_due.is_some()),
+            },
+            DispatchRule::Priority => b.priority.cmp(&a.priority),
+        };
+        by_rule.then(a_since.cmp(&b_since))
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use rand::Rng;
+use rand_distr::{Distribution as _, Exp, Normal, Triangular};
+use serde::{Deserialize, Serialize};
+
+/// Largest parameter in seconds. Samples stay far below what a `Duration` holds.
+const MAX_SECONDS: f32 = 1e9;
+
+/// Random duration with its parameters in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Distribution {
+    Constant(f32),
+    Exponential { mean: f32 },
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+    Triangular { min: f32, mode: f32, max: f32 },
+}
+
+impl Distribution {
+    /// Random duration, negative samples of a normal distribution become zero
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let seconds = match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Exponential { mean } => Exp::new(1.0 / mean)
+                .expect("The mean of an exponential distribution must be positive")
+                .sample(rng),
+            Distribution::Uniform { min, max } => rng.random_range(min..=max),
+            Distribution::Normal { mean, std_dev } => Normal::new(mean, std_dev)
+                .expect("The standard deviation must not be negative")
+                .sample(rng),
+            Distribution::Triangular { min, mode, max } => Triangular::new(min, max, mode)
+                .expect("A triangular distribution needs min <= mode <= max")
+                .sample(rng),
+        };
+        Duration::from_secs_f32(seconds.max(0.0))
+    }
+    pub fn mean(&self) -> Duration {
+        let seconds = match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Exponential { mean } | Distribution::Normal { mean, .. } => mean,
+            Distribution::Uniform { min, max } => (min + max) / 2.0,
+            Distribution::Triangular { min, mode, max } => (min + mode + max) / 3.0,
+        };
+        Duration::from_secs_f32(seconds.max(0.0))
+    }
+    /// The same shape stretched by `factor` in time
+    pub fn scaled(&self, factor: f32) -> Distribution {
+        match *self {
+            Distribution::Constant(value) => Distribution::Constant(value * factor),
+            Distribution::Exponential { mean } => Distribution::Exponential {
+                mean: mean * factor,
+            },
+            Distribution::Uniform { min, max } => Distribution::Uniform {
+                min: min * factor,
+                max: max * factor,
+            },
+            Distribution::Normal { mean, std_dev } => Distribution::Normal {
+                mean: mean * factor,
+                std_dev: std_dev * factor,
+            },
+            Distribution::Triangular { min, mode, max } => Distribution::Triangular {
+                min: min * factor,
+                mode: mode * factor,
+                max: max * factor,
+            },
+        }
+    }
+    fn parameters(&self) -> Vec<f32> {
+        match *self {
+            Distribution::Constant(value) => vec![value],
+            Distribution::Exponential { mean } => vec![mean],
+            Distribution::Uniform { min, max } => vec![min, max],
+            Distribution::Normal { mean, std_dev } => vec![mean, std_dev],
+            Distribution::Triangular { min, mode, max } => vec![min, mode, max],
+        }
+    }
+    /// Why the parameters can not be sampled from, if they can not
+    pub fn check(&self) -> Result<(), String> {
+        if self
+            .parameters()
+            .iter()
+            .any(|parameter| !parameter.is_finite() || parameter.abs() > MAX_SECONDS)
+        {
+            return Err(format!(
+                "every parameter must be a number from -{MAX_SECONDS:e} to {MAX_SECONDS:e}"
+            ));
+        }
+        let problem = match *self {
+            Distribution::Constant(value) if value < 0.0 => "the time must not be negative",
+            Distribution::Exponential { mean } if mean <= 0.0 => "the mean must be positive",
+            Distribution::Uniform { min, max } if min > max => "min must not be above max",
+            Distribution::Normal { std_dev, .. } if std_dev < 0.0 => {
+                "the standard deviation must not be negative"
+            }
+            Distribution::Triangular { min, mode, max } if !(min <= mode && mode <= max) => {
+                "the distribution needs min <= mode <= max"
+            }
+            _ => return Ok(()),
+        };
+        Err(problem.to_string())
+    }
+}
+
+/// Shape of a distribution that is only given by its mean, like a MTBF
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistributionKind {
+    Constant,
+    #[default]
+    Exponential,
+    /// Uniform between zero and twice the mean
+    Uniform,
+}
+
+impl DistributionKind {
+    pub fn with_mean(self, mean: f32) -> Distribution {
+        match self {
+            DistributionKind::Constant => Distribution::Constant(mean),
+            DistributionKind::Exponential => Distribution::Exponential { mean },
+            DistributionKind::Uniform => Distribution::Uniform {
+                min: 0.0,
+                max: 2.0 * mean,
+            },
+        }
+    }
+}
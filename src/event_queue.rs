@@ -0,0 +1,58 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::time_manager::VirtualInstant;
+
+/// What is due for a product at a scheduled time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductEvent {
+    /// The machine is done with the product
+    StepFinished,
+    /// The product reached the machine it was dispatched to
+    Arrived,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Entry {
+    at: VirtualInstant,
+    /// Entries due at the same time are taken in the order they were scheduled
+    sequence: u64,
+    product: u64,
+    event: ProductEvent,
+}
+
+/// Product events of the running scenario ordered by the time they are due
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventQueue {
+    entries: BinaryHeap<Reverse<Entry>>,
+    next_sequence: u64,
+}
+
+impl EventQueue {
+    pub fn schedule(&mut self, at: VirtualInstant, product: u64, event: ProductEvent) {
+        self.entries.push(Reverse(Entry {
+            at,
+            sequence: self.next_sequence,
+            product,
+            event,
+        }));
+        self.next_sequence += 1;
+    }
+
+    /// Time of the earliest event
+    pub fn next_time(&self) -> Option<VirtualInstant> {
+        self.entries.peek().map(|Reverse(entry)| entry.at)
+    }
+
+    /// Take the earliest event if it is due at `now`
+    pub fn pop_due(&mut self, now: VirtualInstant) -> Option<(u64, ProductEvent)> {
+        if self.next_time()? > now {
+            return None;
+        }
+        self.entries
+            .pop()
+            .map(|Reverse(entry)| (entry.product, entry.event))
+    }
+}
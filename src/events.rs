@@ -0,0 +1,98 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{board::ModuleState, constants::MAX_PRODUCT_IN_STORAGE};
+
+/// What happened, with the details that only some events have
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    ScenarioStarted {
+        scenario: String,
+        /// Modules shown as storages
+        storages: Vec<[i32; 2]>,
+        /// Most products any of the storages holds
+        #[serde(default = "default_storage_capacity")]
+        storage_capacity: u32,
+    },
+    /// Another scenario is selected, at the time the stopped one ran for
+    ScenarioStopped {
+        scenario: String,
+    },
+    PhaseChanged {
+        phase: String,
+    },
+    ProductSpawned {
+        plan: String,
+        color: [f32; 3],
+        /// Cells of the first step, the product is shown at the first one
+        path: Vec<[i32; 2]>,
+    },
+    /// The product left for the machine of its next step along the cells
+    ProductMoved {
+        path: Vec<[i32; 2]>,
+    },
+    /// The product arrived at the machine and is processed
+    StepStarted,
+    StepFinished,
+    /// The step is finished but none of the machines of the next step is free
+    WaitingForMachine,
+    ProductFinished,
+    MachineStateChanged {
+        previous: ModuleState,
+        state: ModuleState,
+    },
+}
+
+/// Logs written before the capacity was recorded come from the real board
+fn default_storage_capacity() -> u32 {
+    MAX_PRODUCT_IN_STORAGE
+}
+
+/// Something that happened on the board
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Virtual seconds since the scenario started
+    pub time: f64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos: Option<[i32; 2]>,
+}
+
+type Subscriber = Box<dyn FnMut(&Event)>;
+
+/// Hands every event of the board to its subscribers
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&Event) + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+    pub fn publish(&mut self, event: &Event) {
+        for subscriber in &mut self.subscribers {
+            subscriber(event);
+        }
+    }
+}
+
+/// Subscriber writing every event as one line of JSON
+pub fn json_lines(mut writer: impl Write + 'static) -> impl FnMut(&Event) {
+    move |event| {
+        let written = serde_json::to_writer(&mut writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(writer));
+        if let Err(err) = written {
+            eprintln!("Failed to write event: {err}");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use crate::{
+    board::{Board, Scenario, Snapshot},
+    events::EventBus,
+    time_manager::ClockSource,
+};
+
+/// Settings of a run without macroquad or blinkt
+#[derive(Debug, Clone)]
+pub struct HeadlessConfig {
+    /// Scenario to run, counted from 1 like the scenario button
+    pub scenario: usize,
+    /// Virtual time to simulate
+    pub duration: Duration,
+    /// Virtual time per `Board::update`
+    pub time_step: Duration,
+    /// Overrides the seed of the scenario
+    pub seed: Option<u64>,
+    /// Products a storage holds, `MAX_PRODUCT_IN_STORAGE` on the real board
+    pub storage_capacity: u32,
+    /// Products the module at a position works on at once instead of one
+    pub module_capacities: Vec<([i32; 2], u32)>,
+    /// Factor on the production time of every step
+    pub time_scale: f32,
+}
+
+/// Build a board driven by a manual clock and start the scenario on it
+pub fn new_board(
+    scenarios: &[Scenario],
+    scenario: &Scenario,
+    storage_capacity: u32,
+    events: EventBus,
+) -> Board {
+    let mut board = Board::with_clock_source(ClockSource::Manual);
+    board.events = events;
+    for product_plan in scenarios.iter().flat_map(Scenario::product_plans) {
+        board.set_storage(product_plan.clone(), storage_capacity);
+    }
+    board.set_scenario(scenario.clone());
+    board
+}
+
+/// Simulate the board in steps of `time_step` until `duration` has passed, without
+/// drawing. Runs as fast as the machine allows and gives the same result every
+/// time, whatever the time step.
+pub fn run(board: &mut Board, duration: Duration, time_step: Duration) {
+    assert!(
+        !time_step.is_zero(),
+        "The time step of a headless run must not be zero"
+    );
+    while board.time_manager.now().inner() < duration {
+        let remaining = duration - board.time_manager.now().inner();
+        board.time_manager.advance(time_step.min(remaining));
+        board.advance();
+    }
+}
+
+/// Run as configured, continuing from the snapshot if there is one. The scenario
+/// of the snapshot is run then, and `duration` is the virtual time to stop at.
+pub fn run_config(
+    scenarios: &[Scenario],
+    config: &HeadlessConfig,
+    events: EventBus,
+    snapshot: Option<Snapshot>,
+) -> Result<Board, String> {
+    let scenario = match &snapshot {
+        Some(snapshot) => scenarios
+            .iter()
+            .find(|scenario| scenario.name == snapshot.scenario)
+            .ok_or_else(|| format!("There is no scenario {}", snapshot.scenario))?,
+        None => scenarios
+            .get(config.scenario.wrapping_sub(1))
+            .ok_or_else(|| format!("There is no scenario {}", config.scenario))?,
+    };
+    let mut scenario = scenario.clone();
+    if let Some(seed) = config.seed {
+        scenario.seed = seed;
+    }
+    scenario.scale_step_times(config.time_scale);
+    let mut board = new_board(scenarios, &scenario, config.storage_capacity, events);
+    if let Some(snapshot) = snapshot {
+        board.restore(scenario, snapshot)?;
+    }
+    for &(pos, capacity) in &config.module_capacities {
+        if !Board::inbounds(pos) {
+            return Err(format!("Module {pos:?} is not on the board"));
+        }
+        board[pos].max_production = capacity;
+    }
+    run(&mut board, config.duration, config.time_step);
+    Ok(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{constants::MAX_PRODUCT_IN_STORAGE, scenario_file};
+
+    /// KPIs and order report of every scenario after a run with `time_step`
+    fn reports(time_step: Duration) -> Vec<String> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios.toml");
+        let scenarios = scenario_file::load_scenarios(&path).unwrap();
+        (1..=scenarios.len())
+            .map(|scenario| {
+                let config = HeadlessConfig {
+                    scenario,
+                    duration: Duration::from_secs(300),
+                    time_step,
+                    seed: None,
+                    storage_capacity: MAX_PRODUCT_IN_STORAGE,
+                    module_capacities: Vec::new(),
+                    time_scale: 1.0,
+                };
+                let board = run_config(&scenarios, &config, EventBus::default(), None).unwrap();
+                format!("{}{}", board.metrics, board.order_book.report(board.now()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn result_does_not_depend_on_time_step() {
+        let expected = reports(Duration::from_millis(10));
+        for time_step in [Duration::from_millis(370), Duration::from_secs(5)] {
+            assert_eq!(reports(time_step), expected, "time step {time_step:?}");
+        }
+    }
+}
@@ -1,76 +1,80 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::{
-    constants::{EPSILON, STEP_SIZE},
-    time_manager::TimeManager,
-};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+use crate::{constants::STEP_SIZE, time_manager::VirtualInstant};
+
+/// Position of a product on the board as a function of the virtual time. It
+/// travels along its way at `STEP_SIZE` cells per second from `start` on and
+/// stands at the last corner once it arrived.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LigthPoint {
-    current: [f32; 2],
-    target: [f32; 2],
-    remaining_path: VecDeque<[i32; 2]>,
-    time_manager: TimeManager,
+    /// Cell centers along the way, with a corner wherever it turns
+    corners: Vec<[f32; 2]>,
+    start: VirtualInstant,
 }
 
-impl Iterator for LigthPoint {
-    type Item = [f32; 2];
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut dir = [
-            self.target[0] - self.current[0],
-            self.target[1] - self.current[1],
-        ];
-
-        if dir[0].abs() < EPSILON && dir[1].abs() < EPSILON {
-            let path = self.remaining_path.pop_front()?;
-
-            self.target[0] = path[0] as f32 + 0.5;
-            self.target[1] = path[1] as f32 + 0.5;
-        }
-
-        if dir[1].abs() < EPSILON {
-            dir[1] = 0.;
-        } else {
-            dir[0] = 0.;
-        }
-        let length = dir[0].hypot(dir[1]);
-        let new_length = length.clamp(0.0, STEP_SIZE * self.time_manager.last_virtual_delta());
-        let mult = new_length / length;
-        if mult.is_finite() {
-            self.current[0] += dir[0] * mult;
-            self.current[1] += dir[1] * mult;
-        }
+fn center(cell: [i32; 2]) -> [f32; 2] {
+    [cell[0] as f32 + 0.5, cell[1] as f32 + 0.5]
+}
 
-        Some(self.current)
-    }
+fn length(from: [f32; 2], to: [f32; 2]) -> f32 {
+    (to[0] - from[0]).abs() + (to[1] - from[1]).abs()
 }
 
 impl LigthPoint {
-    pub fn new(mut path: VecDeque<[i32; 2]>, time_manager: TimeManager) -> Self {
-        let current = path.pop_front().unwrap();
-        let target = path.pop_front().unwrap();
-
+    /// Standing at the first cell of the path
+    pub fn new(path: VecDeque<[i32; 2]>) -> Self {
         Self {
-            current: [current[0] as f32 + 0.5, current[1] as f32 + 0.5],
-            target: [target[0] as f32 + 0.5, target[1] as f32 + 0.5],
-            remaining_path: path,
-            time_manager,
+            corners: vec![center(path[0])],
+            start: VirtualInstant::zero(),
         }
     }
-    pub fn current_i32x2(&self) -> [i32; 2] {
-        [self.current[0] as i32, self.current[1] as i32]
+    /// Cell the point stands at, or will once it arrived
+    pub fn target(&self) -> [i32; 2] {
+        let last = self.corners.last().unwrap();
+        [last[0] as i32, last[1] as i32]
     }
-    pub fn current(&self) -> [f32; 2] {
-        self.current
+    /// Travel from where the point stands along the cells of `path` from `start` on.
+    /// Between two cells it moves along the y axis first.
+    pub fn set_new_target(&mut self, path: VecDeque<[i32; 2]>, start: VirtualInstant) {
+        let mut corners = vec![*self.corners.last().unwrap()];
+        for to in path.into_iter().map(center) {
+            let from = *corners.last().unwrap();
+            if from[0] != to[0] && from[1] != to[1] {
+                corners.push([from[0], to[1]]);
+            }
+            corners.push(to);
+        }
+        self.corners = corners;
+        self.start = start;
     }
-    pub fn target(&self) -> [i32; 2] {
-        *self.remaining_path.back().unwrap()
+    /// Time from the start to the arrival at the last cell
+    pub fn travel_time(&self) -> Duration {
+        let distance = self
+            .corners
+            .windows(2)
+            .map(|pair| length(pair[0], pair[1]))
+            .sum::<f32>();
+        Duration::from_secs_f32(distance / STEP_SIZE)
     }
-    pub fn set_new_target(&mut self, path: VecDeque<[i32; 2]>) {
-        self.remaining_path = path;
-
-        let next_target = self.remaining_path.pop_front().unwrap();
-        self.target = [next_target[0] as f32 + 0.5, next_target[1] as f32 + 0.5];
+    pub fn position(&self, now: VirtualInstant) -> [f32; 2] {
+        let last = *self.corners.last().unwrap();
+        if now >= self.start + self.travel_time() {
+            return last;
+        }
+        let mut distance = now.inner().saturating_sub(self.start.inner()).as_secs_f32() * STEP_SIZE;
+        for pair in self.corners.windows(2) {
+            let length = length(pair[0], pair[1]);
+            if distance < length {
+                let fraction = distance / length;
+                return [
+                    pair[0][0] + (pair[1][0] - pair[0][0]) * fraction,
+                    pair[0][1] + (pair[1][1] - pair[0][1]) * fraction,
+                ];
+            }
+            distance -= length;
+        }
+        last
     }
 }
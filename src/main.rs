@@ -1,51 +1,241 @@
 use std::{
-    cell::LazyCell,
-    collections::VecDeque,
-    sync::{LazyLock, OnceLock},
-    task::{Context, Poll, Waker},
-    time::{Duration, Instant},
+    io::LineWriter,
+    path::{Path, PathBuf},
 };
+#[cfg(not(target_arch = "x86_64"))]
 use std::{
-    io::{BufRead, Write},
-    thread::sleep,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use serialport::{SerialPortInfo, SerialPortType};
-
 #[cfg(not(target_arch = "x86_64"))]
 use blinkt::{Blinkt, BlinktSpi};
-#[cfg(target_arch = "x86_64")]
-use board::color_to_vec3;
 use constants::*;
-use ligth_point::LigthPoint;
 #[cfg(target_arch = "x86_64")]
 use macroquad::prelude::*;
 
 use crate::{
-    board::{Board, MachineStateChange, Scenario},
-    product::ProductPlan,
-    product::{Product, Step},
+    board::{Board, Scenario, Snapshot},
+    cli::{Args, Mode},
+    control::{Controller, SerialControl},
+    devices::DeviceConfig,
+    events::{EventBus, json_lines},
+    replay::Replay,
     time_manager::TimeManager,
-    time_manager::VirtualInstant,
 };
 
-const BAUD_RATE: u32 = 115_200;
-
+mod batch;
 mod board;
+mod cli;
 mod constants;
+mod control;
+#[cfg(target_arch = "x86_64")]
+mod dashboard;
+mod devices;
+mod dispatching;
+mod distribution;
+mod event_queue;
+mod events;
+mod headless;
 mod ligth_point;
+mod metrics;
 mod module;
+mod orders;
 mod product;
+mod reliability;
+mod replay;
+mod routing;
+mod scenario_file;
+mod sweep;
 mod time_manager;
+#[cfg(target_arch = "x86_64")]
+mod timeline;
+mod validation;
+
+fn main() {
+    let args = Args::parse();
+    match args.mode {
+        Mode::ListDevices => {
+            let device_config = DeviceConfig::load(&args.device_file);
+            devices::print_devices(&devices::discover(&device_config, &[]));
+        }
+        Mode::Headless(config) => {
+            let scenarios = load_scenarios(&args.scenario_file);
+            let events = event_bus(args.event_log.as_deref());
+            let snapshot = args.restore.as_deref().map(load_snapshot);
+            match headless::run_config(&scenarios, &config, events, snapshot) {
+                Ok(board) => {
+                    println!("Simulated {}", board.time_manager.format_time());
+                    print!("{}", board.metrics);
+                    print!("{}", board.order_book.report(board.now()));
+                    if let Some(path) = &args.snapshot
+                        && let Err(err) = board.snapshot().save(path)
+                    {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Mode::Batch(config) => {
+            let scenarios = load_scenarios(&args.scenario_file);
+            let result = batch::run(&scenarios, &config).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            });
+            print!("{result}");
+            write_output(config.csv.as_deref(), |file| result.write_csv(file));
+            write_output(config.json.as_deref(), |file| result.write_json(file));
+        }
+        Mode::Sweep(config) => {
+            let scenarios = load_scenarios(&args.scenario_file);
+            let result = sweep::run(&scenarios, &config).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            });
+            print!("{result}");
+            write_output(config.csv.as_deref(), |file| result.write_csv(file));
+            write_output(config.json.as_deref(), |file| result.write_json(file));
+        }
+        Mode::Validate => {
+            let scenarios = load_scenarios(&args.scenario_file);
+            println!(
+                "{}: {} scenarios are valid",
+                args.scenario_file.display(),
+                scenarios.len()
+            );
+        }
+        Mode::Run => run(
+            DeviceConfig::load(&args.device_file),
+            load_scenarios(&args.scenario_file),
+            event_bus(args.event_log.as_deref()),
+            None,
+            args.restore.as_deref().map(load_snapshot),
+            args.snapshot
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_SNAPSHOT_FILE)),
+        ),
+        Mode::Replay(path) => {
+            let replay = Replay::load(&path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            });
+            run(
+                DeviceConfig::load(&args.device_file),
+                Vec::new(),
+                event_bus(args.event_log.as_deref()),
+                Some(replay),
+                None,
+                PathBuf::from(DEFAULT_SNAPSHOT_FILE),
+            )
+        }
+    }
+}
+
+/// Load and validate the scenarios, exiting with all problems found
+fn load_scenarios(path: &Path) -> Vec<Scenario> {
+    let scenarios = match scenario_file::load_scenarios(path) {
+        Ok(scenarios) => scenarios,
+        Err(err) => {
+            eprintln!("Failed to load scenarios: {err}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(errors) = validation::validate(&scenarios) {
+        eprintln!("Invalid scenarios in {}:", path.display());
+        for error in errors {
+            eprintln!("  {error}");
+        }
+        std::process::exit(1);
+    }
+    scenarios
+}
+
+/// Read the snapshot to restore, exiting if that fails
+fn load_snapshot(path: &Path) -> Snapshot {
+    Snapshot::load(path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    })
+}
+
+/// Write a result file if its path was given, exiting when that fails
+fn write_output<E: std::fmt::Display>(
+    path: Option<&Path>,
+    write: impl FnOnce(std::fs::File) -> Result<(), E>,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let written = match std::fs::File::create(path) {
+        Ok(file) => write(file).map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = written {
+        eprintln!("Failed to write {}: {err}", path.display());
+        std::process::exit(1);
+    }
+}
+
+/// Event bus writing JSON lines to the given file or stdout, exiting if the file
+/// can not be created
+fn event_bus(path: Option<&Path>) -> EventBus {
+    let mut events = EventBus::default();
+    match path {
+        None => {}
+        Some(path) if path == Path::new("-") => events.subscribe(json_lines(std::io::stdout())),
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => events.subscribe(json_lines(LineWriter::new(file))),
+            Err(err) => {
+                eprintln!("Failed to create {}: {err}", path.display());
+                std::process::exit(1);
+            }
+        },
+    }
+    events
+}
 
 #[cfg(target_arch = "x86_64")]
-#[macroquad::main("Board")]
-async fn main() {
-    main_inner().await;
+fn run(
+    device_config: DeviceConfig,
+    scenarios: Vec<Scenario>,
+    events: EventBus,
+    replay: Option<Replay>,
+    restore: Option<Snapshot>,
+    snapshot_file: PathBuf,
+) {
+    macroquad::Window::new(
+        "Board",
+        main_inner(
+            device_config,
+            scenarios,
+            events,
+            replay,
+            restore,
+            snapshot_file,
+        ),
+    );
 }
 #[cfg(not(target_arch = "x86_64"))]
-fn main() {
-    let future = std::pin::pin!(main_inner());
+fn run(
+    device_config: DeviceConfig,
+    scenarios: Vec<Scenario>,
+    events: EventBus,
+    replay: Option<Replay>,
+    restore: Option<Snapshot>,
+    snapshot_file: PathBuf,
+) {
+    let future = std::pin::pin!(main_inner(
+        device_config,
+        scenarios,
+        events,
+        replay,
+        restore,
+        snapshot_file
+    ));
     let mut context = Context::from_waker(Waker::noop());
     match future.poll(&mut context) {
         Poll::Pending => {}
@@ -53,10 +243,20 @@ fn main() {
     }
 }
 
-async fn main_inner() {
+/// Show the scenarios on screen or the LEDs, or the replay if there is one.
+/// The first scenario is started, or the one of the snapshot to restore.
+async fn main_inner(
+    device_config: DeviceConfig,
+    scenarios: Vec<Scenario>,
+    events: EventBus,
+    mut replay: Option<Replay>,
+    restore: Option<Snapshot>,
+    snapshot_file: PathBuf,
+) {
     #[cfg(target_arch = "x86_64")]
     board::Board::set_screen_size();
     let mut board = Board::new();
+    board.events = events;
 
     #[cfg(not(target_arch = "x86_64"))]
     let mut blinkt = Blinkt::with_spi(
@@ -69,24 +269,51 @@ async fn main_inner() {
         .unwrap(),
         X_NUM_MODULES * Y_NUM_MODULES * (7 + 6),
     );
-    board.set_storage(STEPS_TOP_NORMAL.clone());
-    board.set_storage(STEPS_BOTTOM_NORMAL.clone());
-    board.set_scenario(BOTTOM_SUPPLY_DIFFICULTY.clone());
+    for product_plan in scenarios.iter().flat_map(Scenario::product_plans) {
+        board.set_storage(product_plan.clone(), MAX_PRODUCT_IN_STORAGE);
+    }
+    if let Some(scenario) = scenarios.first() {
+        board.set_scenario(scenario.clone());
+    }
+    if let Some(snapshot) = restore {
+        let restored = scenarios
+            .iter()
+            .find(|scenario| scenario.name == snapshot.scenario)
+            .ok_or_else(|| format!("There is no scenario {}", snapshot.scenario))
+            .and_then(|scenario| board.restore(scenario.clone(), snapshot));
+        if let Err(err) = restored {
+            eprintln!("Failed to restore the snapshot: {err}");
+        }
+    }
+
+    let mut controller = Controller::new(SerialControl::start(device_config));
+    #[cfg(target_arch = "x86_64")]
+    let mut timeline = timeline::Timeline::default();
 
     loop {
+        #[cfg(not(target_arch = "x86_64"))]
         let start_time = Instant::now();
 
         #[cfg(target_arch = "x86_64")]
         {
             // Handle keyboard input for time control
             handle_time_controls(&mut board.time_manager);
+            if replay.is_none() {
+                timeline.handle_input(&mut board);
+            }
             clear_background(GRAY);
         }
 
+        controller.update(&mut board, &scenarios);
+
         board.reset(LED_OFF_COLOR);
 
-        board.update();
+        match &mut replay {
+            Some(replay) => replay.update(&mut board),
+            None => board.update(),
+        }
         board.draw_modules();
+        board.draw_status_pixel(controller.status_color());
 
         #[cfg(not(target_arch = "x86_64"))]
         for (pixel, mut color) in blinkt.iter_mut().zip(board.colors()) {
@@ -103,16 +330,36 @@ async fn main_inner() {
         #[cfg(target_arch = "x86_64")]
         {
             for key in get_keys_pressed() {
-                match key {
-                    KeyCode::Key7 => board.set_scenario(Scenario::starting_scenario().clone()),
-                    KeyCode::Key8 => board.set_scenario(BOTTOM_SUPPLY_DIFFICULTY.clone()),
-                    KeyCode::Key9 => board.set_scenario(MAINTENANCE.clone()),
-                    _ => {}
+                if key == KeyCode::S && replay.is_none() {
+                    match board.snapshot().save(&snapshot_file) {
+                        Ok(()) => eprintln!("Saved snapshot to {}", snapshot_file.display()),
+                        Err(err) => eprintln!("{err}"),
+                    }
+                    continue;
+                }
+                let scenario_index = match key {
+                    KeyCode::Key7 => 0,
+                    KeyCode::Key8 => 1,
+                    KeyCode::Key9 => 2,
+                    _ => continue,
+                };
+                if let Some(scenario) = scenarios.get(scenario_index) {
+                    board.set_scenario(scenario.clone());
                 }
             }
             board.draw_on_screen();
             // Draw speed indicator
             draw_speed_indicator(&board.time_manager, vec2(10.0, 10.0));
+            draw_connection_status(&controller, vec2(10.0, 170.0));
+            // The replay has no metrics to show and no snapshots to go back to
+            match &replay {
+                Some(replay) => draw_replay_status(&board, replay, vec2(10.0, 240.0)),
+                None => {
+                    dashboard::draw_dashboard(&board);
+                    timeline.record(&board);
+                    timeline.draw(&board);
+                }
+            }
             next_frame().await
         }
 
@@ -121,182 +368,6 @@ async fn main_inner() {
             (start_time + Duration::from_secs(1) / 100).saturating_duration_since(Instant::now()),
         );
     }
-
-    // let (speed_button, scenario_button) = init();
-
-    // let (Some(speed_button), Some(scenario_button)) = (speed_button, scenario_button) else {
-    //     panic!("No speed and scenario button found");
-    // };
-
-    // let mut speed_button_port = serialport::new(speed_button.port_name, BAUD_RATE)
-    //     .open()
-    //     .unwrap();
-    // let mut scenario_button_port = serialport::new(scenario_button.port_name, BAUD_RATE)
-    //     .open()
-    //     .unwrap();
-
-    // let mut speed_button_reader =
-    //     std::io::BufReader::new(speed_button_port.try_clone().unwrap()).lines();
-    // let mut scenario_button_reader =
-    //     std::io::BufReader::new(scenario_button_port.try_clone().unwrap()).lines();
-
-    // loop {
-    //     if let Some(Ok(speed)) = speed_button_reader.next() {
-    //         println!("Speed: {}", speed);
-    //     }
-    //     if let Some(Ok(scenario)) = scenario_button_reader.next() {
-    //         println!("Scenario: {}", scenario);
-    //         match scenario
-    //             .to_ascii_lowercase()
-    //             .trim()
-    //             .split_ascii_whitespace()
-    //             .collect::<Vec<&str>>()
-    //             .as_slice()
-    //         {
-    //             ["boot"] => {
-    //                 speed_button_port.write_all(b"boot\n").unwrap();
-    //             }
-    //             ["scenario", scenario_num] => {
-    //                 let message = format!("scenario {}\n", scenario_num);
-
-    //                 speed_button_port.write_all(message.as_bytes()).unwrap();
-
-    //                 match *scenario_num {
-    //                     "1" => {
-    //                         board.set_scenario(Scenario::starting_scenario().clone());
-    //                     }
-    //                     "2" => {
-    //                         board.set_scenario(BOTTOM_SUPPLY_DIFFICULTY.clone());
-    //                     }
-    //                     "3" => {
-    //                         board.set_scenario(MAINTENANCE.clone());
-    //                     }
-    //                     _ => {
-    //                         println!("Invalid scenario number");
-    //                     }
-    //                 }
-    //             }
-    //             ["start"] => {
-    //                 speed_button_port.write_all(b"start\n").unwrap();
-    //                 for i in 0..11 {
-    //                     let percentage = i as f32 * 0.1;
-    //                     let message = format!("progress {}\n", percentage);
-    //                     speed_button_port.write_all(message.as_bytes()).unwrap();
-    //                     sleep(Duration::from_millis(500));
-    //                 }
-    //             }
-    //             ["resume"] => {
-    //                 speed_button_port.write_all(b"resume\n").unwrap();
-    //                 board.time_manager.resume();
-    //             }
-    //             ["pause"] => {
-    //                 speed_button_port.write_all(b"pause\n").unwrap();
-    //                 board.time_manager.pause();
-    //             }
-    //             ["stop"] => {
-    //                 speed_button_port.write_all(b"stop\n").unwrap();
-    //                 board.set_scenario(Scenario::starting_scenario());
-    //             }
-    //             _ => {}
-    //         }
-    //     }
-
-    //     let start_time = Instant::now();
-
-    //     #[cfg(target_arch = "x86_64")]
-    //     {
-    //         // Handle keyboard input for time control
-    //         handle_time_controls(&mut board.time_manager);
-    //         clear_background(GRAY);
-    //     }
-
-    //     board.reset(LED_OFF_COLOR);
-
-    //     board.update();
-
-    //     #[cfg(not(target_arch = "x86_64"))]
-    //     for (pixel, mut color) in blinkt.iter_mut().zip(board.colors()) {
-    //         pixel.set_rgbb(
-    //             (color.red * 255.0) as u8,
-    //             (color.green * 255.0) as u8,
-    //             (color.blue * 255.0) as u8,
-    //             1.0,
-    //         );
-    //     }
-    //     #[cfg(not(target_arch = "x86_64"))]
-    //     blinkt.show().unwrap();
-
-    //     #[cfg(target_arch = "x86_64")]
-    //     {
-    //         for key in get_keys_pressed() {
-    //             match key {
-    //                 KeyCode::Key7 => board.set_scenario(Scenario::starting_scenario().clone()),
-    //                 KeyCode::Key8 => board.set_scenario(BOTTOM_SUPPLY_DIFFICULTY.clone()),
-    //                 KeyCode::Key9 => board.set_scenario(MAINTENANCE.clone()),
-    //                 _ => {}
-    //             }
-    //         }
-    //         board.draw();
-    //         // Draw speed indicator
-    //         draw_speed_indicator(&board.time_manager, vec2(10.0, 10.0));
-    //         next_frame().await
-    //     }
-
-    //     #[cfg(not(target_arch = "x86_64"))]
-    //     std::thread::sleep(
-    //         (start_time + Duration::from_secs(1) / 100).saturating_duration_since(Instant::now()),
-    //     );
-    // }
-}
-
-fn init() -> (Option<SerialPortInfo>, Option<SerialPortInfo>) {
-    let ports = serialport::available_ports().expect("no ports found");
-    let mut speed_button = None;
-    let mut scenario_button = None;
-    for port in ports {
-        let SerialPortType::UsbPort(ref usb_port_info) = port.port_type else {
-            // Skip non-USB ports
-            continue;
-        };
-        if usb_port_info.vid != 0x303A || usb_port_info.pid != 0x1001 {
-            // Skip specific USB device
-            continue;
-        }
-
-        println!("Found usb: {:?}", usb_port_info);
-        // println!("Found usb: {:?}", usb_port_info.product);
-        // read_serial_to_std_out(port.clone());
-
-        // if usb_port_info.product == Some("Serielles USB-Gerät (COM5)".to_string()) {
-        //     scenario_button = Some(port);
-        // } else {
-        //     speed_button = Some(port)
-        // }
-        if usb_port_info.serial_number == Some("80:65:99:BD:16:0C".to_string()) {
-            println!("Found usb: {:?}", port);
-            scenario_button = Some(port);
-        } else if usb_port_info.serial_number == Some("80:65:99:BD:1D:B0".to_string()) {
-            println!("Found usb: {:?}", port);
-            speed_button = Some(port);
-        }
-    }
-    (speed_button, scenario_button)
-}
-
-fn read_serial_to_std_out(serial_port_info: SerialPortInfo) {
-    let port = serialport::new(serial_port_info.port_name, BAUD_RATE)
-        // .
-        .open()
-        .expect("Failed to open serial_port");
-
-    for line in std::io::BufReader::new(port).lines() {
-        if line.is_err() {
-            // println!("Error reading line: {:?}", line.err());
-            continue;
-        }
-        println!("{}", line.unwrap());
-        std::io::stdout().flush().unwrap();
-    }
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -354,6 +425,8 @@ fn draw_speed_indicator(time_manager: &TimeManager, position: Vec2) {
         "↑/↓: Fine adjust speed",
         "Space: Pause/Resume",
         "R: Reset time",
+        "S: Save snapshot",
+        "Drag timeline: Rewind",
     ];
 
     for (i, line) in help_text.iter().enumerate() {
@@ -366,3 +439,44 @@ fn draw_speed_indicator(time_manager: &TimeManager, position: Vec2) {
         );
     }
 }
+
+#[cfg(target_arch = "x86_64")]
+/// Draw what the replay is showing
+fn draw_replay_status(board: &Board, replay: &Replay, position: Vec2) {
+    let status = if replay.is_finished() {
+        " (finished)"
+    } else {
+        ""
+    };
+    draw_text(
+        &format!(
+            "Replay of {}: {}{status}",
+            board.scenario_name(),
+            board.phase_name()
+        ),
+        position.x,
+        position.y,
+        20.0,
+        WHITE,
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+/// Draw whether the serial buttons are connected
+fn draw_connection_status(controller: &Controller, position: Vec2) {
+    use crate::{control::ConnectionState, devices::DeviceRole};
+
+    for (i, role) in DeviceRole::ALL.into_iter().enumerate() {
+        let (text, color) = match controller.connection_state(role) {
+            ConnectionState::Connected => ("connected", macroquad::prelude::GREEN),
+            ConnectionState::Disconnected => ("reconnecting...", macroquad::prelude::RED),
+        };
+        draw_text(
+            &format!("{role}: {text}"),
+            position.x,
+            position.y + i as f32 * 18.0,
+            16.0,
+            color,
+        );
+    }
+}
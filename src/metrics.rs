@@ -0,0 +1,347 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{Module, ModuleState},
+    constants::{X_NUM_MODULES, Y_NUM_MODULES},
+    time_manager::VirtualInstant,
+};
+
+/// Interval between two samples of the time series
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a module spent a time slice on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Utilization {
+    /// A product is being processed
+    Busy,
+    /// Processing is done but the product can not move on to the next machine
+    Blocked,
+    /// Functional but without a product to work on
+    Starved,
+    /// In maintenance or broken
+    Down,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleTimes {
+    pub busy: Duration,
+    pub blocked: Duration,
+    pub starved: Duration,
+    pub down: Duration,
+}
+
+impl ModuleTimes {
+    fn add(&mut self, utilization: Utilization, delta: Duration) {
+        match utilization {
+            Utilization::Busy => self.busy += delta,
+            Utilization::Blocked => self.blocked += delta,
+            Utilization::Starved => self.starved += delta,
+            Utilization::Down => self.down += delta,
+        }
+    }
+    pub fn total(&self) -> Duration {
+        self.busy + self.blocked + self.starved + self.down
+    }
+    /// Fraction of the time the module was busy
+    pub fn utilization(&self) -> f32 {
+        ratio(self.busy, self.total())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlanMetrics {
+    pub started: u32,
+    pub completed: u32,
+    pub total_lead_time: Duration,
+}
+
+impl PlanMetrics {
+    pub fn average_lead_time(&self) -> Option<Duration> {
+        (self.completed > 0).then(|| self.total_lead_time / self.completed)
+    }
+}
+
+/// Everything recorded while one scenario phase was active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseMetrics {
+    pub phase: String,
+    pub start: VirtualInstant,
+    pub duration: Duration,
+    pub plans: BTreeMap<String, PlanMetrics>,
+    /// Products in the system integrated over time, in product-seconds
+    wip_seconds: f64,
+    pub modules: [[ModuleTimes; X_NUM_MODULES]; Y_NUM_MODULES],
+}
+
+impl PhaseMetrics {
+    fn new(phase: &str, start: VirtualInstant) -> Self {
+        Self {
+            phase: phase.to_string(),
+            start,
+            duration: Duration::ZERO,
+            plans: BTreeMap::new(),
+            wip_seconds: 0.0,
+            modules: Default::default(),
+        }
+    }
+    pub fn completed(&self) -> u32 {
+        self.plans.values().map(|plan| plan.completed).sum()
+    }
+    /// Completed products per minute
+    pub fn throughput(&self) -> f32 {
+        let minutes = self.duration.as_secs_f32() / 60.0;
+        if minutes > 0.0 {
+            self.completed() as f32 / minutes
+        } else {
+            0.0
+        }
+    }
+    /// Time average of the products in the system
+    pub fn average_wip(&self) -> f32 {
+        if self.duration.is_zero() {
+            0.0
+        } else {
+            (self.wip_seconds / self.duration.as_secs_f64()) as f32
+        }
+    }
+    pub fn average_lead_time(&self) -> Option<Duration> {
+        let completed = self.completed();
+        let total = self
+            .plans
+            .values()
+            .map(|plan| plan.total_lead_time)
+            .sum::<Duration>();
+        (completed > 0).then(|| total / completed)
+    }
+}
+
+/// Point of the time series shown in the dashboard
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: VirtualInstant,
+    /// Products completed since the scenario started
+    pub completed: u32,
+    pub wip: usize,
+}
+
+/// Production KPIs of the running scenario, split by scenario phase
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    pub phases: Vec<PhaseMetrics>,
+    pub current_wip: usize,
+    pub completed: u32,
+    pub samples: Vec<Sample>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn phase_mut(&mut self, phase: &str, now: VirtualInstant) -> &mut PhaseMetrics {
+        if self
+            .phases
+            .last()
+            .is_none_or(|current| current.phase != phase)
+        {
+            self.phases.push(PhaseMetrics::new(phase, now));
+        }
+        self.phases.last_mut().unwrap()
+    }
+
+    pub fn product_started(&mut self, phase: &str, now: VirtualInstant, plan: &str) {
+        let phase = self.phase_mut(phase, now);
+        phase.plans.entry(plan.to_string()).or_default().started += 1;
+    }
+
+    pub fn product_finished(
+        &mut self,
+        phase: &str,
+        now: VirtualInstant,
+        plan: &str,
+        lead_time: Duration,
+    ) {
+        self.completed += 1;
+        let phase = self.phase_mut(phase, now);
+        let plan = phase.plans.entry(plan.to_string()).or_default();
+        plan.completed += 1;
+        plan.total_lead_time += lead_time;
+    }
+
+    /// Account the time slice from `start` on, in which nothing changed, to the work
+    /// in progress and the module states
+    pub fn record(
+        &mut self,
+        phase: &str,
+        start: VirtualInstant,
+        delta: Duration,
+        wip: usize,
+        utilization: &[[Utilization; X_NUM_MODULES]; Y_NUM_MODULES],
+    ) {
+        self.current_wip = wip;
+        let end = start + delta;
+        loop {
+            let time = self
+                .samples
+                .last()
+                .map_or(start, |sample| sample.time + SAMPLE_INTERVAL);
+            if time > end {
+                break;
+            }
+            self.samples.push(Sample {
+                time,
+                completed: self.completed,
+                wip,
+            });
+        }
+        let phase = self.phase_mut(phase, start);
+        phase.duration += delta;
+        phase.wip_seconds += wip as f64 * delta.as_secs_f64();
+        for (times, utilization) in phase
+            .modules
+            .as_flattened_mut()
+            .iter_mut()
+            .zip(utilization.as_flattened())
+        {
+            times.add(*utilization, delta);
+        }
+    }
+
+    /// Time covered by all phases
+    pub fn duration(&self) -> Duration {
+        self.phases.iter().map(|phase| phase.duration).sum()
+    }
+    /// Completed products per minute over all phases
+    pub fn throughput(&self) -> f32 {
+        let minutes = self.duration().as_secs_f32() / 60.0;
+        if minutes > 0.0 {
+            self.completed as f32 / minutes
+        } else {
+            0.0
+        }
+    }
+    /// Time average of the products in the system over all phases
+    pub fn average_wip(&self) -> f32 {
+        let duration = self.duration();
+        if duration.is_zero() {
+            0.0
+        } else {
+            let wip_seconds = self
+                .phases
+                .iter()
+                .map(|phase| phase.wip_seconds)
+                .sum::<f64>();
+            (wip_seconds / duration.as_secs_f64()) as f32
+        }
+    }
+    pub fn average_lead_time(&self) -> Option<Duration> {
+        let total = self
+            .phases
+            .iter()
+            .flat_map(|phase| phase.plans.values())
+            .map(|plan| plan.total_lead_time)
+            .sum::<Duration>();
+        (self.completed > 0).then(|| total / self.completed)
+    }
+
+    /// Completed products per minute over the `window` before every sample
+    pub fn throughput_series(&self, window: Duration) -> Vec<(VirtualInstant, f32)> {
+        let mut start = 0;
+        self.samples
+            .iter()
+            .map(|sample| {
+                while (sample.time - self.samples[start].time).inner() > window {
+                    start += 1;
+                }
+                let first = &self.samples[start];
+                let minutes = (sample.time - first.time).inner().as_secs_f32() / 60.0;
+                let throughput = if minutes > 0.0 {
+                    (sample.completed - first.completed) as f32 / minutes
+                } else {
+                    0.0
+                };
+                (sample.time, throughput)
+            })
+            .collect()
+    }
+}
+
+/// Classify a module given what the products at it are doing
+pub fn classify(module: &Module, processing: bool, blocked: bool) -> Utilization {
+    if !matches!(module.state, ModuleState::Functional) {
+        Utilization::Down
+    } else if processing {
+        Utilization::Busy
+    } else if blocked {
+        Utilization::Blocked
+    } else {
+        Utilization::Starved
+    }
+}
+
+fn ratio(part: Duration, total: Duration) -> f32 {
+    if total.is_zero() {
+        0.0
+    } else {
+        part.as_secs_f32() / total.as_secs_f32()
+    }
+}
+
+fn format_lead_time(lead_time: Option<Duration>) -> String {
+    lead_time.map_or("-".to_string(), |lead_time| {
+        format!("{:.1}s", lead_time.as_secs_f32())
+    })
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Work in progress: {}", self.current_wip)?;
+        for phase in &self.phases {
+            writeln!(
+                f,
+                "Phase {} ({:.1}s from {:.1}s):",
+                phase.phase,
+                phase.duration.as_secs_f32(),
+                phase.start.inner().as_secs_f32()
+            )?;
+            let lead_time = format_lead_time(phase.average_lead_time());
+            writeln!(
+                f,
+                "  throughput {:.2}/min, average WIP {:.2}, average lead time {lead_time}",
+                phase.throughput(),
+                phase.average_wip()
+            )?;
+            for (name, plan) in &phase.plans {
+                let lead_time = format_lead_time(plan.average_lead_time());
+                writeln!(
+                    f,
+                    "  plan {name}: started {}, completed {}, average lead time {lead_time}",
+                    plan.started, plan.completed
+                )?;
+            }
+            for (i, times) in phase.modules.as_flattened().iter().enumerate() {
+                if times.busy.is_zero() && times.blocked.is_zero() && times.down.is_zero() {
+                    continue;
+                }
+                let total = times.total();
+                writeln!(
+                    f,
+                    "  module [{}, {}]: busy {:.0}%, blocked {:.0}%, starved {:.0}%, down {:.0}%",
+                    i % X_NUM_MODULES,
+                    i / X_NUM_MODULES,
+                    times.utilization() * 100.0,
+                    ratio(times.blocked, total) * 100.0,
+                    ratio(times.starved, total) * 100.0,
+                    ratio(times.down, total) * 100.0,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
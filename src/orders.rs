@@ -0,0 +1,171 @@
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{product::ProductPlan, time_manager::VirtualInstant};
+
+/// Decides when a new product may start
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ReleasePolicy {
+    /// Start a product whenever the first machine is free
+    #[default]
+    Push,
+    /// Keep at most `wip_cap` products on the board
+    Conwip { wip_cap: usize },
+    /// Every step of a plan has a loop of `cards` kanban cards. A product takes a
+    /// card of a step when it moves there and hands it back when it moves on, so it
+    /// only starts or moves on once the step it goes to has a free card.
+    Kanban { cards: usize },
+}
+
+impl ReleasePolicy {
+    /// Whether a product may start while there are `wip` products in total and
+    /// `at_first_step` products of its plan at the first step
+    pub fn allows(&self, wip: usize, at_first_step: usize) -> bool {
+        match *self {
+            ReleasePolicy::Push => true,
+            ReleasePolicy::Conwip { wip_cap } => wip < wip_cap,
+            ReleasePolicy::Kanban { .. } => self.allows_move(at_first_step),
+        }
+    }
+
+    /// Whether a product may move on to a step that `at_step` products of its plan
+    /// are at or moving to
+    pub fn allows_move(&self, at_step: usize) -> bool {
+        match *self {
+            ReleasePolicy::Kanban { cards } => at_step < cards,
+            _ => true,
+        }
+    }
+}
+
+/// A fixed number of products of one plan, ordered for a time after the scenario start
+#[derive(Clone)]
+pub struct Order {
+    pub name: String,
+    pub plan: ProductPlan,
+    pub quantity: u32,
+    pub release: Duration,
+    pub due: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct OrderProgress {
+    pub order: Order,
+    pub released: u32,
+    pub completed: u32,
+    /// Products finished after the due date
+    pub late: u32,
+    pub finished: Option<VirtualInstant>,
+}
+
+impl OrderProgress {
+    /// How late the last product of the order was finished, or for an open order
+    /// how far `now` is past the due date. Zero if it is in time.
+    pub fn tardiness(&self, now: VirtualInstant) -> Option<Duration> {
+        let due = self.order.due?;
+        let end = self.finished.unwrap_or(now);
+        Some(end.inner().saturating_sub(due))
+    }
+}
+
+/// Orders of the running scenario and how far they got
+#[derive(Clone, Default)]
+pub struct OrderBook {
+    pub orders: Vec<OrderProgress>,
+}
+
+impl OrderBook {
+    pub fn new(orders: &[Order]) -> Self {
+        Self {
+            orders: orders
+                .iter()
+                .map(|order| OrderProgress {
+                    order: order.clone(),
+                    released: 0,
+                    completed: 0,
+                    late: 0,
+                    finished: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Orders that are released at `now` and still have products to start
+    pub fn open(&self, now: VirtualInstant) -> impl Iterator<Item = (usize, &Order)> {
+        self.orders
+            .iter()
+            .enumerate()
+            .filter(move |(_, progress)| {
+                progress.order.release <= now.inner() && progress.released < progress.order.quantity
+            })
+            .map(|(i, progress)| (i, &progress.order))
+    }
+
+    /// Next time after `now` an order with products to start is released
+    pub fn next_release(&self, now: VirtualInstant) -> Option<VirtualInstant> {
+        self.orders
+            .iter()
+            .filter(|progress| progress.released < progress.order.quantity)
+            .map(|progress| VirtualInstant::zero() + progress.order.release)
+            .filter(|release| *release > now)
+            .min()
+    }
+
+    pub fn product_released(&mut self, order: usize) {
+        self.orders[order].released += 1;
+    }
+
+    pub fn product_finished(&mut self, order: usize, now: VirtualInstant) {
+        let progress = &mut self.orders[order];
+        progress.completed += 1;
+        if progress.order.due.is_some_and(|due| now.inner() > due) {
+            progress.late += 1;
+        }
+        if progress.completed == progress.order.quantity {
+            progress.finished = Some(now);
+        }
+    }
+
+    /// Report of the orders as they stand at `now`
+    pub fn report(&self, now: VirtualInstant) -> OrderReport<'_> {
+        OrderReport { book: self, now }
+    }
+}
+
+pub struct OrderReport<'a> {
+    book: &'a OrderBook,
+    now: VirtualInstant,
+}
+
+fn format_seconds(time: Option<Duration>) -> String {
+    time.map_or("-".to_string(), |time| {
+        format!("{:.1}s", time.as_secs_f32())
+    })
+}
+
+impl Display for OrderReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for progress in &self.book.orders {
+            let order = &progress.order;
+            writeln!(
+                f,
+                "Order {} ({} x {}): released {}, completed {} ({} late), due {}, finished {}, tardiness {}",
+                order.name,
+                order.quantity,
+                order.plan.name,
+                progress.released,
+                progress.completed,
+                progress.late,
+                format_seconds(order.due),
+                format_seconds(progress.finished.map(|finished| finished.inner())),
+                format_seconds(progress.tardiness(self.now)),
+            )?;
+        }
+        Ok(())
+    }
+}
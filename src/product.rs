@@ -1,29 +1,43 @@
 use std::{collections::VecDeque, time::Duration};
 
 use palette::Srgb;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    board::Board,
-    ligth_point::LigthPoint,
-    time_manager::{TimeManager, VirtualInstant},
+    board::Board, distribution::Distribution, event_queue::ProductEvent, events::EventKind,
+    ligth_point::LigthPoint, time_manager::VirtualInstant,
 };
 
 #[derive(Clone)]
 pub struct ProductPlan {
+    pub name: String,
     pub steps: Vec<Step>,
     pub color: Srgb,
+    /// Higher priorities get contended machines first with `DispatchRule::Priority`
+    pub priority: i32,
+    /// Time after the start a product should be finished
+    pub due_in: Option<Duration>,
 }
 impl ProductPlan {
-    pub fn new(steps: Vec<Step>, color: Srgb) -> Self {
-        Self { steps, color }
+    pub fn new(name: String, steps: Vec<Step>, color: Srgb) -> Self {
+        Self {
+            name,
+            steps,
+            color,
+            priority: 0,
+            due_in: None,
+        }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Step {
     path: Vec<[i32; 2]>,
     maschine_pos: [i32; 2],
-    production_time: Duration,
+    /// Machines that can do the step instead when `maschine_pos` is down or busy
+    alternatives: Vec<[i32; 2]>,
+    production_time: Distribution,
     is_storage: bool,
 }
 impl Step {
@@ -36,11 +50,25 @@ impl Step {
         Self {
             path,
             maschine_pos,
-            production_time: Duration::from_millis((time_in_seconds * 1000.0) as u64),
+            alternatives: Vec::new(),
+            production_time: Distribution::Constant(time_in_seconds),
             is_storage: storage,
         }
     }
-    fn path(&self) -> VecDeque<[i32; 2]> {
+    /// Production time drawn for every product anew
+    pub fn with_production_time(mut self, production_time: Distribution) -> Self {
+        self.production_time = production_time;
+        self
+    }
+    pub fn scale_production_time(&mut self, factor: f32) {
+        self.production_time = self.production_time.scaled(factor);
+    }
+    pub fn with_alternatives(mut self, alternatives: Vec<[i32; 2]>) -> Self {
+        self.alternatives = alternatives;
+        self
+    }
+    /// Cells the product travels through to the machine, ending with the machine
+    pub fn path(&self) -> VecDeque<[i32; 2]> {
         let mut path = VecDeque::from(self.path.clone());
         path.push_back(self.maschine_pos);
         path
@@ -52,9 +80,13 @@ impl Step {
     pub fn maschine_pos(&self) -> [i32; 2] {
         self.maschine_pos
     }
+    /// The planned machine followed by its alternatives
+    pub fn machines(&self) -> impl Iterator<Item = [i32; 2]> + '_ {
+        std::iter::once(self.maschine_pos).chain(self.alternatives.iter().copied())
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 enum State {
     Waiting {
         until: VirtualInstant,
@@ -65,80 +97,187 @@ enum State {
         target_wait: Duration,
     },
     WaitingForFreeMaschine {
+        since: VirtualInstant,
         next_step: Step,
+        /// Whether the product already reported that no machine was free
+        blocked: bool,
     },
 }
 
+/// What a product is currently doing, as seen by the metrics
+pub enum Activity {
+    Processing([i32; 2]),
+    /// Processing is done but the next machine can not take the product yet
+    Blocked([i32; 2]),
+    Moving,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Product {
+    /// Counted up from zero in every scenario
+    pub id: u64,
     remaining_steps: Vec<Step>,
     ligth_point: LigthPoint,
     pub color: Srgb,
+    pub plan: String,
+    pub created: VirtualInstant,
+    pub priority: i32,
+    pub due: Option<VirtualInstant>,
+    /// Index of the order in the order book the product was started for
+    pub order: Option<usize>,
     state: State,
+    /// Whether the product only visited planned machines so far, so the
+    /// precomputed path of the next step starts where the product is
+    on_planned_route: bool,
 }
 impl Product {
-    pub fn new(color: Srgb, mut steps: Vec<Step>, time_manager: &TimeManager) -> Self {
+    pub fn new(plan: &ProductPlan, id: u64, now: VirtualInstant, rng: &mut impl Rng) -> Self {
+        let mut steps = plan.steps.clone();
         assert!(steps.len() >= 2, "Fertigungsauftag needs atleast 2 steps");
         let step = steps.remove(0);
 
         let path = step.path();
 
-        let ligth_point = LigthPoint::new(path, time_manager.clone());
+        let ligth_point = LigthPoint::new(path);
         Self {
+            id,
             state: State::Waiting {
-                until: time_manager.now() + step.production_time,
+                until: now + step.production_time.sample(rng),
                 next_step: steps.remove(0),
             },
             remaining_steps: steps,
             ligth_point,
-            color,
+            color: plan.color,
+            plan: plan.name.clone(),
+            created: now,
+            priority: plan.priority,
+            due: plan.due_in.map(|due_in| now + due_in),
+            order: None,
+            on_planned_route: true,
         }
     }
-    pub fn finish(&self, board: &mut Board) {
-        board[self.ligth_point.current_i32x2()].in_production -= 1;
-    }
-    fn waiting_in_storage(&self) -> [f32; 2] {
-        self.ligth_point.current()
+    pub fn activity(&self) -> Activity {
+        match self.state {
+            State::Waiting { .. } => Activity::Processing(self.ligth_point.target()),
+            State::WaitingForFreeMaschine { .. } => Activity::Blocked(self.ligth_point.target()),
+            State::Moving { .. } => Activity::Moving,
+        }
     }
-    pub fn next(&mut self, board: &mut Board) -> Option<[f32; 2]> {
+    /// Since when the product waits for a machine and how long the machine will need on average
+    pub fn waiting_for_machine(&self) -> Option<(VirtualInstant, Duration)> {
         match &self.state {
-            State::Waiting { until, next_step } => {
-                board[self.ligth_point.current_i32x2()].in_storage += 1;
-                if board.time_manager.now() >= *until {
-                    self.state = State::WaitingForFreeMaschine {
-                        next_step: next_step.clone(),
-                    };
-                }
-                Some(self.waiting_in_storage())
-            }
-            State::WaitingForFreeMaschine { next_step } => {
-                if !board[next_step.maschine_pos].can_receiv_product() {
-                    board[self.ligth_point.current_i32x2()].in_storage += 1;
-                    return Some(self.waiting_in_storage());
-                };
-                board[self.ligth_point.current_i32x2()].in_production -= 1;
-
-                self.ligth_point.set_new_target(next_step.path());
-                board[next_step.maschine_pos].in_production += 1;
-                self.state = State::Moving {
-                    target_wait: next_step.production_time,
-                };
-                Some(self.ligth_point.current())
+            State::WaitingForFreeMaschine {
+                since, next_step, ..
+            } => Some((*since, next_step.production_time.mean())),
+            _ => None,
+        }
+    }
+    /// Position of the product the first time it could not be dispatched while waiting
+    pub fn report_blocked(&mut self) -> Option<[i32; 2]> {
+        match &mut self.state {
+            State::WaitingForFreeMaschine { blocked, .. } if !*blocked => {
+                *blocked = true;
+                Some(self.ligth_point.target())
             }
-            State::Moving { target_wait } => {
-                if let Some(pos) = self.ligth_point.next() {
-                    Some(pos)
-                } else {
-                    if self.remaining_steps.is_empty() {
-                        return None;
-                    }
-                    let step = self.remaining_steps.remove(0);
-                    self.state = State::Waiting {
-                        until: board.time_manager.now() + *target_wait,
-                        next_step: step,
-                    };
-                    Some(self.ligth_point.current())
+            _ => None,
+        }
+    }
+    /// Move on to the first free machine of the next step, if the product waits for one
+    pub fn dispatch(&mut self, board: &mut Board) -> bool {
+        let State::WaitingForFreeMaschine { next_step, .. } = &self.state else {
+            return false;
+        };
+        let current = self.ligth_point.target();
+        let Some((machine, path)) = next_step
+            .machines()
+            .filter(|machine| board[*machine].can_receiv_product())
+            .find_map(|machine| {
+                if machine == next_step.maschine_pos && self.on_planned_route {
+                    return Some((machine, next_step.path()));
                 }
-            }
+                let mut path = VecDeque::from(board.layout().route(current, machine)?);
+                path.push_back(machine);
+                Some((machine, path))
+            })
+        else {
+            return false;
+        };
+        board[current].in_production -= 1;
+
+        self.on_planned_route = self.on_planned_route && machine == next_step.maschine_pos;
+        let moved = EventKind::ProductMoved {
+            path: path.iter().copied().collect(),
+        };
+        board.emit(moved, Some(self.id), Some(current));
+        self.ligth_point.set_new_target(path, board.now());
+        board[machine].in_production += 1;
+        self.state = State::Moving {
+            target_wait: next_step.production_time.sample(board.rng()),
+        };
+        let arrival = board.now() + self.ligth_point.travel_time();
+        board.schedule(arrival, self.id, ProductEvent::Arrived);
+        true
+    }
+    /// Leave the last machine, returning where it was
+    pub fn finish(&self, board: &mut Board) -> [i32; 2] {
+        let pos = self.ligth_point.target();
+        board[pos].in_production -= 1;
+        pos
+    }
+    /// When the machine is done with the product, while it is processed
+    pub fn step_end(&self) -> Option<VirtualInstant> {
+        match self.state {
+            State::Waiting { until, .. } => Some(until),
+            _ => None,
         }
     }
+    /// The machine is done, the product waits for one of the next step
+    pub fn finish_step(&mut self, board: &mut Board) {
+        let State::Waiting { next_step, .. } = &self.state else {
+            return;
+        };
+        self.state = State::WaitingForFreeMaschine {
+            since: board.now(),
+            next_step: next_step.clone(),
+            blocked: false,
+        };
+        let pos = self.ligth_point.target();
+        board.emit(EventKind::StepFinished, Some(self.id), Some(pos));
+    }
+    /// Start the next step at the machine reached, `false` if the product is done
+    pub fn arrive(&mut self, board: &mut Board) -> bool {
+        let State::Moving { target_wait } = self.state else {
+            return true;
+        };
+        if self.remaining_steps.is_empty() {
+            return false;
+        }
+        let until = board.now() + target_wait;
+        self.state = State::Waiting {
+            until,
+            next_step: self.remaining_steps.remove(0),
+        };
+        board.schedule(until, self.id, ProductEvent::StepFinished);
+        let pos = self.ligth_point.target();
+        board.emit(EventKind::StepStarted, Some(self.id), Some(pos));
+        true
+    }
+    /// Steps of the plan after the one the product is at or moving to
+    pub fn steps_left(&self) -> usize {
+        match self.state {
+            State::Moving { .. } => self.remaining_steps.len(),
+            _ => self.remaining_steps.len() + 1,
+        }
+    }
+    /// Cell the product is parked at, `None` while it travels
+    pub fn parked_at(&self) -> Option<[i32; 2]> {
+        match self.state {
+            State::Moving { .. } => None,
+            _ => Some(self.ligth_point.target()),
+        }
+    }
+    /// Where to show the product at `now`
+    pub fn light_point(&self, now: VirtualInstant) -> [f32; 2] {
+        self.ligth_point.position(now)
+    }
 }
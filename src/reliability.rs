@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::ModuleState, constants::MIN_BREAKDOWN_TIME, distribution::Distribution,
+    time_manager::VirtualInstant,
+};
+
+/// Failure behaviour of one module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reliability {
+    pub pos: [i32; 2],
+    pub time_between_failures: Distribution,
+    pub time_to_repair: Distribution,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Breakdown {
+    reliability: Reliability,
+    /// Time of the next failure, or of the repair while broken
+    next: VirtualInstant,
+    broken: bool,
+}
+
+/// Random failures and repairs of the modules of a running scenario
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Breakdowns {
+    breakdowns: Vec<Breakdown>,
+}
+
+impl Breakdowns {
+    pub fn new(reliabilities: &[Reliability], rng: &mut impl Rng) -> Self {
+        Self {
+            breakdowns: reliabilities
+                .iter()
+                .map(|reliability| Breakdown {
+                    reliability: reliability.clone(),
+                    next: VirtualInstant::zero() + reliability.time_between_failures.sample(rng),
+                    broken: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Time of the next failure or repair
+    pub fn next_change(&self) -> Option<VirtualInstant> {
+        self.breakdowns.iter().map(|breakdown| breakdown.next).min()
+    }
+
+    /// State changes that are due at `now`. A module that is not functional when
+    /// its failure is due, e.g. in maintenance, fails after the next random interval
+    /// instead, and a repair leaves a module alone that was changed by the scenario.
+    #[must_use]
+    pub fn update(
+        &mut self,
+        now: VirtualInstant,
+        state_of: impl Fn([i32; 2]) -> ModuleState,
+        rng: &mut impl Rng,
+    ) -> Vec<([i32; 2], ModuleState)> {
+        let mut changes = Vec::new();
+        for breakdown in &mut self.breakdowns {
+            let pos = breakdown.reliability.pos;
+            let mut state = state_of(pos);
+            while breakdown.next <= now {
+                let reliability = &breakdown.reliability;
+                if breakdown.broken {
+                    breakdown.broken = false;
+                    if matches!(state, ModuleState::Broken) {
+                        state = ModuleState::Functional;
+                        changes.push((pos, state.clone()));
+                    }
+                    breakdown.next += sample(&reliability.time_between_failures, rng);
+                } else if matches!(state, ModuleState::Functional) {
+                    breakdown.broken = true;
+                    state = ModuleState::Broken;
+                    changes.push((pos, state.clone()));
+                    breakdown.next += sample(&reliability.time_to_repair, rng);
+                } else {
+                    breakdown.next += sample(&reliability.time_between_failures, rng);
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Random time of a distribution, at least `MIN_BREAKDOWN_TIME`
+fn sample(distribution: &Distribution, rng: &mut impl Rng) -> Duration {
+    distribution.sample(rng).max(MIN_BREAKDOWN_TIME)
+}
@@ -0,0 +1,280 @@
+use std::{collections::VecDeque, path::Path, time::Duration};
+
+use palette::Srgb;
+
+use crate::{
+    board::{Board, Phase, Scenario},
+    events::{Event, EventKind},
+    ligth_point::LigthPoint,
+    time_manager::VirtualInstant,
+};
+
+/// Product as far as the event log tells
+struct ReplayedProduct {
+    id: u64,
+    color: Srgb,
+    ligth_point: LigthPoint,
+    /// Machine the product is at or moving to
+    machine: [i32; 2],
+    /// Whether the product left for its machine and did not start the step yet
+    moving: bool,
+}
+
+/// Reject events the replay cannot show, like paths leaving the board
+fn check(event: &Event) -> Result<(), String> {
+    if event.time < 0.0 {
+        return Err(format!("negative time {}", event.time));
+    }
+    let path = match &event.kind {
+        EventKind::ProductSpawned { path, .. } if path.is_empty() => {
+            return Err("`product_spawned` with an empty path".to_string());
+        }
+        EventKind::ProductSpawned { path, .. } | EventKind::ProductMoved { path } => path,
+        _ => return Ok(()),
+    };
+    match path.iter().find(|cell| !Board::inbounds(**cell)) {
+        Some(cell) => Err(format!("cell {cell:?} is outside the board")),
+        None => Ok(()),
+    }
+}
+
+/// Drives a board from a recorded event log instead of the simulation
+pub struct Replay {
+    events: Vec<Event>,
+    next: usize,
+    products: Vec<ReplayedProduct>,
+}
+
+impl Replay {
+    pub fn new(events: Vec<Event>) -> Result<Self, String> {
+        if !matches!(
+            events.first(),
+            Some(Event {
+                kind: EventKind::ScenarioStarted { .. },
+                ..
+            })
+        ) {
+            return Err("the event log does not start with a `scenario_started` event".to_string());
+        }
+        for (i, event) in events.iter().enumerate() {
+            check(event).map_err(|err| format!("event {}: {err}", i + 1))?;
+        }
+        Ok(Self {
+            events,
+            next: 0,
+            products: Vec::new(),
+        })
+    }
+
+    /// Read an event log written with `--events`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let events = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| format!("{}:{}: {err}", path.display(), i + 1))
+            })
+            .collect::<Result<Vec<Event>, _>>()?;
+        Self::new(events).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    /// Advance the board time, apply the events that happened until then and
+    /// draw the products
+    pub fn update(&mut self, board: &mut Board) {
+        // Scenarios are selected between two updates, before the time advances
+        self.apply_due(board, true);
+        board.advance();
+        self.apply_due(board, false);
+
+        for module in board.modules.as_flattened_mut() {
+            module.in_production = 0;
+        }
+        let now = board.now();
+        for product in &self.products {
+            board[product.machine].in_production += 1;
+            if !product.moving {
+                board[product.ligth_point.target()].in_storage += 1;
+            }
+            board.draw_light_point(product.ligth_point.position(now), product.color);
+        }
+    }
+
+    /// Apply the events up to the current time, either only the scenario
+    /// changes or everything up to the next scenario change
+    fn apply_due(&mut self, board: &mut Board, scenario_changes: bool) {
+        while let Some(event) = self.events.get(self.next) {
+            let is_scenario_change = matches!(
+                event.kind,
+                EventKind::ScenarioStarted { .. } | EventKind::ScenarioStopped { .. }
+            );
+            if is_scenario_change != scenario_changes
+                || event.time > board.time_manager.now().inner().as_secs_f64()
+            {
+                break;
+            }
+            let event = event.clone();
+            self.next += 1;
+            self.apply(event, board);
+        }
+    }
+
+    fn apply(&mut self, event: Event, board: &mut Board) {
+        let product = event
+            .product
+            .and_then(|id| self.products.iter().position(|product| product.id == id));
+        match event.kind {
+            EventKind::ScenarioStarted {
+                scenario,
+                storages,
+                storage_capacity,
+            } => {
+                for module in board.modules.as_flattened_mut() {
+                    module.storage = storages.contains(&module.pos);
+                    module.max_production = if module.storage { storage_capacity } else { 1 };
+                }
+                self.products.clear();
+                let phases = self.upcoming_phases();
+                board.set_scenario(Scenario::new(scenario, phases));
+            }
+            // The scenario of the replay goes through its phases by itself
+            EventKind::ScenarioStopped { .. } | EventKind::PhaseChanged { .. } => {}
+            EventKind::ProductSpawned { color, path, .. } => {
+                let Some(id) = event.product else {
+                    return;
+                };
+                let path = VecDeque::from(path);
+                self.products.push(ReplayedProduct {
+                    id,
+                    color: Srgb::new(color[0], color[1], color[2]),
+                    machine: path.back().copied().unwrap_or_default(),
+                    ligth_point: LigthPoint::new(path),
+                    moving: false,
+                });
+            }
+            EventKind::ProductMoved { path } => {
+                if let Some(product) = product.map(|i| &mut self.products[i]) {
+                    product.machine = path.last().copied().unwrap_or(product.machine);
+                    let start = VirtualInstant::zero() + Duration::from_secs_f64(event.time);
+                    product.ligth_point.set_new_target(path.into(), start);
+                    product.moving = true;
+                }
+            }
+            EventKind::StepStarted => {
+                if let Some(product) = product.map(|i| &mut self.products[i]) {
+                    product.moving = false;
+                }
+            }
+            EventKind::StepFinished | EventKind::WaitingForMachine => {}
+            EventKind::ProductFinished => {
+                if let Some(i) = product {
+                    self.products.remove(i);
+                }
+            }
+            EventKind::MachineStateChanged { state, .. } => {
+                if let Some(pos) = event.pos.filter(|pos| Board::inbounds(*pos)) {
+                    board.set_module_state(pos, state);
+                }
+            }
+        }
+    }
+
+    /// Phases of the scenario that just started, timed by its `phase_changed` events
+    fn upcoming_phases(&self) -> Vec<Phase> {
+        let changes = self.events[self.next..]
+            .iter()
+            .take_while(|event| {
+                !matches!(
+                    event.kind,
+                    EventKind::ScenarioStarted { .. } | EventKind::ScenarioStopped { .. }
+                )
+            })
+            .filter_map(|event| match &event.kind {
+                EventKind::PhaseChanged { phase } => Some((event.time, phase.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut phases = changes
+            .iter()
+            .enumerate()
+            .map(|(i, (start, name))| Phase {
+                name: name.clone(),
+                duration: changes
+                    .get(i + 1)
+                    .map(|(end, _)| Duration::from_secs_f64(end - start)),
+                product_plans: Vec::new(),
+                machine_state_changes: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        if phases.is_empty() {
+            phases.push(Phase {
+                name: "Replay".to_string(),
+                duration: None,
+                product_plans: Vec::new(),
+                machine_state_changes: Vec::new(),
+            });
+        }
+        phases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(lines: &[&str]) -> Result<Replay, String> {
+        let start = r#"{"time":0.0,"event":"scenario_started","scenario":"Test","storages":[]}"#;
+        let events = std::iter::once(start)
+            .chain(lines.iter().copied())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        Replay::new(events)
+    }
+
+    #[test]
+    fn accepts_paths_on_the_board() {
+        assert!(
+            replay(&[
+                r#"{"time":0.0,"event":"product_spawned","plan":"A","color":[1,0,0],"path":[[0,0]],"product":1}"#,
+                r#"{"time":1.0,"event":"product_moved","path":[[0,1],[1,1]],"product":1}"#,
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_an_empty_spawn_path() {
+        let err = replay(&[
+            r#"{"time":0.0,"event":"product_spawned","plan":"A","color":[1,0,0],"path":[],"product":1}"#,
+        ])
+        .err()
+        .unwrap();
+        assert!(err.starts_with("event 2:"), "{err}");
+    }
+
+    #[test]
+    fn rejects_cells_outside_the_board() {
+        assert!(
+            replay(&[
+                r#"{"time":0.0,"event":"product_spawned","plan":"A","color":[1,0,0],"path":[[-1,0]],"product":1}"#
+            ])
+            .is_err()
+        );
+        assert!(
+            replay(&[r#"{"time":1.0,"event":"product_moved","path":[[0,0],[99,0]],"product":1}"#])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_a_negative_time() {
+        assert!(replay(&[r#"{"time":-1.0,"event":"step_started","product":1}"#]).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    board::Board,
+    constants::{X_NUM_MODULES, Y_NUM_MODULES},
+};
+
+/// Neighbours are tried in this order, which decides between routes of equal length
+const DIRECTIONS: [[i32; 2]; 4] = [[0, -1], [0, 1], [-1, 0], [1, 0]];
+
+/// Cells of the module grid products may travel through
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// If not empty, products only travel through these cells between two machines,
+    /// even if the machines are next to each other
+    pub conveyors: HashSet<[i32; 2]>,
+    /// Cells no product ever travels through
+    pub blocked: HashSet<[i32; 2]>,
+}
+
+impl Layout {
+    fn traversable(&self, pos: [i32; 2]) -> bool {
+        Board::inbounds(pos)
+            && !self.blocked.contains(&pos)
+            && (self.conveyors.is_empty() || self.conveyors.contains(&pos))
+    }
+
+    /// Shortest route from machine `from` to machine `to`, without the two machines.
+    /// `None` if `to` can not be reached.
+    pub fn route(&self, from: [i32; 2], to: [i32; 2]) -> Option<Vec<[i32; 2]>> {
+        if !Board::inbounds(from) || !Board::inbounds(to) {
+            return None;
+        }
+        if from == to {
+            return Some(Vec::new());
+        }
+        let mut previous = [[None; X_NUM_MODULES]; Y_NUM_MODULES];
+        let mut queue = VecDeque::from([from]);
+        while let Some(pos) = queue.pop_front() {
+            for direction in DIRECTIONS {
+                let next = [pos[0] + direction[0], pos[1] + direction[1]];
+                if next == to && (pos != from || self.conveyors.is_empty()) {
+                    let mut path = Vec::new();
+                    let mut cell = pos;
+                    while cell != from {
+                        path.push(cell);
+                        cell = previous[cell[1] as usize][cell[0] as usize].unwrap();
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                if next != from
+                    && self.traversable(next)
+                    && previous[next[1] as usize][next[0] as usize].is_none()
+                {
+                    previous[next[1] as usize][next[0] as usize] = Some(pos);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbours_need_no_cells_between() {
+        let layout = Layout::default();
+        assert_eq!(layout.route([0, 1], [1, 1]), Some(Vec::new()));
+        assert_eq!(layout.route([2, 2], [2, 2]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn takes_the_shortest_route() {
+        let layout = Layout::default();
+        assert_eq!(layout.route([0, 0], [3, 0]), Some(vec![[1, 0], [2, 0]]));
+    }
+
+    #[test]
+    fn equal_routes_are_decided_up_down_left_right() {
+        let layout = Layout::default();
+        // Down is tried before right, so the route turns along the y axis first
+        assert_eq!(layout.route([0, 0], [1, 1]), Some(vec![[0, 1]]));
+        // Up is tried before left
+        assert_eq!(layout.route([1, 1], [0, 0]), Some(vec![[1, 0]]));
+    }
+
+    #[test]
+    fn goes_around_blocked_cells() {
+        let layout = Layout {
+            blocked: HashSet::from([[1, 0], [1, 1]]),
+            ..Layout::default()
+        };
+        assert_eq!(
+            layout.route([0, 0], [2, 0]),
+            Some(vec![[0, 1], [0, 2], [1, 2], [2, 2], [2, 1]])
+        );
+    }
+
+    #[test]
+    fn only_travels_on_conveyors() {
+        let layout = Layout {
+            conveyors: HashSet::from([[0, 0], [1, 0]]),
+            ..Layout::default()
+        };
+        // Even next to each other the machines are connected through the conveyors
+        assert_eq!(layout.route([0, 1], [1, 1]), Some(vec![[0, 0], [1, 0]]));
+        assert_eq!(layout.route([0, 1], [3, 1]), None);
+    }
+
+    #[test]
+    fn machines_off_the_board_can_not_be_reached() {
+        let layout = Layout::default();
+        assert_eq!(layout.route([0, 0], [6, 0]), None);
+        assert_eq!(layout.route([-1, 0], [0, 0]), None);
+    }
+}
@@ -0,0 +1,668 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use palette::Srgb;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    board::{Anchor, MachineStateChange, ModuleState, Phase, Scenario},
+    constants::*,
+    dispatching::DispatchRule,
+    distribution::{Distribution, DistributionKind},
+    orders::{Order, ReleasePolicy},
+    product::{ProductPlan, Step},
+    reliability::Reliability,
+    routing::Layout,
+};
+
+/// Error while loading a scenario file, pointing at the offending field
+#[derive(Debug)]
+pub struct LoadError {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if !self.field.is_empty() {
+            write!(f, ": {}", self.field)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFileDef {
+    #[serde(default)]
+    layout: LayoutDef,
+    #[serde(default)]
+    modules: Vec<ModuleDef>,
+    #[serde(default)]
+    plans: BTreeMap<String, PlanDef>,
+    #[serde(default)]
+    scenarios: Vec<ScenarioDef>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LayoutDef {
+    #[serde(default)]
+    conveyors: Vec<[i32; 2]>,
+    #[serde(default)]
+    blocked: Vec<[i32; 2]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModuleDef {
+    machine: [i32; 2],
+    capabilities: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlanDef {
+    color: Spanned<ColorDef>,
+    steps: Spanned<Vec<StepDef>>,
+    #[serde(default)]
+    priority: i32,
+    due: Option<Spanned<f32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDef {
+    Named(String),
+    Rgb([f32; 3]),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepDef {
+    time: Spanned<TimeDef>,
+    /// Either a machine or a capability any module declaring it provides
+    machine: Option<Spanned<[i32; 2]>>,
+    capability: Option<Spanned<String>>,
+    /// Computed from the layout if left out
+    path: Option<Vec<[i32; 2]>>,
+    /// Machines that can do the step instead, routed through the layout at runtime
+    alternatives: Option<Spanned<Vec<[i32; 2]>>>,
+    #[serde(default)]
+    storage: bool,
+}
+
+/// Production time in seconds, either fixed or drawn for every product
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expected a time in seconds or a table with a `distribution` and its parameters"
+)]
+enum TimeDef {
+    Constant(f32),
+    Random(DistributionDef),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "distribution", rename_all = "snake_case", deny_unknown_fields)]
+enum DistributionDef {
+    Constant { value: f32 },
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+    Triangular { min: f32, mode: f32, max: f32 },
+    Exponential { mean: f32 },
+}
+
+impl TimeDef {
+    fn distribution(&self) -> Distribution {
+        match *self {
+            TimeDef::Constant(value) | TimeDef::Random(DistributionDef::Constant { value }) => {
+                Distribution::Constant(value)
+            }
+            TimeDef::Random(DistributionDef::Uniform { min, max }) => {
+                Distribution::Uniform { min, max }
+            }
+            TimeDef::Random(DistributionDef::Normal { mean, std_dev }) => {
+                Distribution::Normal { mean, std_dev }
+            }
+            TimeDef::Random(DistributionDef::Triangular { min, mode, max }) => {
+                Distribution::Triangular { min, mode, max }
+            }
+            TimeDef::Random(DistributionDef::Exponential { mean }) => {
+                Distribution::Exponential { mean }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioDef {
+    name: String,
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    dispatching: DispatchRule,
+    #[serde(default)]
+    release: ReleasePolicy,
+    phases: Spanned<Vec<PhaseDef>>,
+    #[serde(default)]
+    reliability: Vec<ReliabilityDef>,
+    #[serde(default)]
+    orders: Vec<OrderDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrderDef {
+    name: Option<String>,
+    plan: Spanned<String>,
+    quantity: u32,
+    release: Option<Spanned<f32>>,
+    due: Option<Spanned<f32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReliabilityDef {
+    machine: [i32; 2],
+    mtbf: Spanned<f32>,
+    mttr: Spanned<f32>,
+    #[serde(default)]
+    distribution: DistributionKind,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhaseDef {
+    name: Spanned<String>,
+    duration: Option<Spanned<f32>>,
+    #[serde(default)]
+    plans: Vec<Spanned<String>>,
+    #[serde(default)]
+    machine_state_changes: Vec<MachineStateChangeDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MachineStateChangeDef {
+    time: Spanned<f32>,
+    #[serde(default)]
+    anchor: Anchor,
+    state: ModuleState,
+    machine: [i32; 2],
+    duration: Option<Spanned<f32>>,
+    repeat: Option<Spanned<f32>>,
+}
+
+/// Load all scenarios defined in a TOML scenario file
+pub fn load_scenarios(path: &Path) -> Result<Vec<Scenario>, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(|err| LoadError {
+        file: path.to_path_buf(),
+        line: None,
+        field: String::new(),
+        message: err.to_string(),
+    })?;
+    parse_scenarios(path, &source)
+}
+
+fn parse_scenarios(path: &Path, source: &str) -> Result<Vec<Scenario>, LoadError> {
+    let error = |span: Option<std::ops::Range<usize>>, field: String, message: String| LoadError {
+        file: path.to_path_buf(),
+        line: span.map(|span| line_of(source, span.start)),
+        field,
+        message,
+    };
+    let seconds = |value: &Spanned<f32>, field: String| {
+        Duration::try_from_secs_f32(*value.get_ref()).map_err(|_| {
+            error(
+                Some(value.span()),
+                field,
+                "must be a finite and not negative number of seconds".to_string(),
+            )
+        })
+    };
+
+    let deserializer = toml::Deserializer::new(source);
+    let file: ScenarioFileDef = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        // `Spanned` values show up as an extra private segment in the path
+        let field = err
+            .path()
+            .to_string()
+            .replace(".$__serde_spanned_private_value", "");
+        let inner = err.into_inner();
+        error(inner.span(), field, inner.message().to_string())
+    })?;
+
+    let layout = Layout {
+        conveyors: file.layout.conveyors.iter().copied().collect(),
+        blocked: file.layout.blocked.iter().copied().collect(),
+    };
+
+    let mut plans = BTreeMap::new();
+    for (name, plan) in &file.plans {
+        let color = match plan.color.get_ref() {
+            ColorDef::Rgb([red, green, blue]) => Srgb::new(*red, *green, *blue),
+            ColorDef::Named(color_name) => named_color(color_name).ok_or_else(|| {
+                error(
+                    Some(plan.color.span()),
+                    format!("plans.{name}.color"),
+                    format!("unknown color `{color_name}`"),
+                )
+            })?,
+        };
+        if plan.steps.get_ref().len() < 2 {
+            return Err(error(
+                Some(plan.steps.span()),
+                format!("plans.{name}.steps"),
+                "a product plan needs at least 2 steps".to_string(),
+            ));
+        }
+        let mut steps = Vec::new();
+        let mut previous: Option<Step> = None;
+        for (i, step) in plan.steps.get_ref().iter().enumerate() {
+            let field = format!("plans.{name}.steps[{i}]");
+            // The first capable module is the planned machine, the others are alternatives
+            let (machine, span, mut alternatives) = match (&step.machine, &step.capability) {
+                (Some(machine), None) => (*machine.get_ref(), machine.span(), Vec::new()),
+                (None, Some(capability)) => {
+                    let mut capable = file
+                        .modules
+                        .iter()
+                        .filter(|module| module.capabilities.contains(capability.get_ref()))
+                        .map(|module| module.machine);
+                    let machine = capable.next().ok_or_else(|| {
+                        error(
+                            Some(capability.span()),
+                            format!("{field}.capability"),
+                            format!("no module provides `{}`", capability.get_ref()),
+                        )
+                    })?;
+                    if step.path.is_some() {
+                        return Err(error(
+                            Some(capability.span()),
+                            format!("{field}.path"),
+                            "a step with a capability can not have a path".to_string(),
+                        ));
+                    }
+                    // The first step always starts at one machine
+                    let alternatives = if previous.is_some() {
+                        capable.collect()
+                    } else {
+                        Vec::new()
+                    };
+                    (machine, capability.span(), alternatives)
+                }
+                _ => {
+                    return Err(error(
+                        Some(plan.steps.span()),
+                        field,
+                        "a step needs either a machine or a capability".to_string(),
+                    ));
+                }
+            };
+            let path = match (&step.path, &previous) {
+                (Some(path), _) => path.clone(),
+                (None, None) => vec![machine],
+                (None, Some(previous)) => {
+                    let from = previous.maschine_pos();
+                    layout.route(from, machine).ok_or_else(|| {
+                        error(
+                            Some(span.clone()),
+                            format!("{field}.machine"),
+                            format!("there is no route from {from:?} to {machine:?}"),
+                        )
+                    })?
+                }
+            };
+            if let Some(extra) = &step.alternatives {
+                if previous.is_none() {
+                    return Err(error(
+                        Some(extra.span()),
+                        format!("{field}.alternatives"),
+                        "the first step can not have alternatives".to_string(),
+                    ));
+                }
+                alternatives.extend(extra.get_ref());
+            }
+            let production_time = step.time.get_ref().distribution();
+            production_time.check().map_err(|message| {
+                error(Some(step.time.span()), format!("{field}.time"), message)
+            })?;
+            let new_step = Step::new(0.0, machine, path, step.storage)
+                .with_production_time(production_time)
+                .with_alternatives(alternatives);
+            // Every machine of the step has to be reachable from every machine of the
+            // previous one, except for the planned route that may have an explicit path
+            if let Some(previous) = &previous {
+                for from in previous.machines() {
+                    for to in new_step.machines() {
+                        let planned = from == previous.maschine_pos() && to == machine;
+                        if !planned && layout.route(from, to).is_none() {
+                            let alternatives_span = step.alternatives.as_ref().map(Spanned::span);
+                            return Err(error(
+                                alternatives_span.or(Some(span.clone())),
+                                format!("{field}.alternatives"),
+                                format!("there is no route from {from:?} to {to:?}"),
+                            ));
+                        }
+                    }
+                }
+            }
+            previous = Some(new_step.clone());
+            steps.push(new_step);
+        }
+        let mut product_plan = ProductPlan::new(name.clone(), steps, color);
+        product_plan.priority = plan.priority;
+        product_plan.due_in = plan
+            .due
+            .as_ref()
+            .map(|due| seconds(due, format!("plans.{name}.due")))
+            .transpose()?;
+        plans.insert(name.as_str(), product_plan);
+    }
+
+    let resolve = |names: &[Spanned<String>], field: &str| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                plans.get(name.get_ref().as_str()).cloned().ok_or_else(|| {
+                    error(
+                        Some(name.span()),
+                        format!("{field}[{i}]"),
+                        format!("unknown plan `{}`", name.get_ref()),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    file.scenarios
+        .iter()
+        .enumerate()
+        .map(|(i, scenario)| {
+            let phase_defs = scenario.phases.get_ref();
+            if phase_defs.is_empty() {
+                return Err(error(
+                    Some(scenario.phases.span()),
+                    format!("scenarios[{i}].phases"),
+                    "a scenario needs at least one phase".to_string(),
+                ));
+            }
+            let mut phases = Vec::new();
+            for (j, phase) in phase_defs.iter().enumerate() {
+                let field = format!("scenarios[{i}].phases[{j}]");
+                if let Some(previous) = j.checked_sub(1).map(|j| &phase_defs[j])
+                    && previous.duration.is_none()
+                {
+                    return Err(error(
+                        Some(phase.name.span()),
+                        format!("{field}.name"),
+                        format!(
+                            "phase is never reached, the phase `{}` before it has no duration",
+                            previous.name.get_ref()
+                        ),
+                    ));
+                }
+                let phase_start = phases
+                    .iter()
+                    .filter_map(|phase: &Phase| phase.duration)
+                    .sum::<Duration>();
+                phases.push(Phase {
+                    name: phase.name.get_ref().clone(),
+                    duration: phase
+                        .duration
+                        .as_ref()
+                        .map(|duration| seconds(duration, format!("{field}.duration")))
+                        .transpose()?,
+                    product_plans: resolve(&phase.plans, &format!("{field}.plans"))?,
+                    machine_state_changes: phase
+                        .machine_state_changes
+                        .iter()
+                        .enumerate()
+                        .map(|(k, change)| {
+                            let field = format!("{field}.machine_state_changes[{k}]");
+                            let duration = change
+                                .duration
+                                .as_ref()
+                                .map(|duration| seconds(duration, format!("{field}.duration")))
+                                .transpose()?;
+                            let repeat = change
+                                .repeat
+                                .as_ref()
+                                .map(|repeat| {
+                                    let period = seconds(repeat, format!("{field}.repeat"))?;
+                                    if period < MIN_REPEAT || period <= duration.unwrap_or_default() {
+                                        return Err(error(
+                                            Some(repeat.span()),
+                                            format!("{field}.repeat"),
+                                            format!(
+                                                "must be at least {}ms and greater than the duration",
+                                                MIN_REPEAT.as_millis()
+                                            ),
+                                        ));
+                                    }
+                                    Ok(period)
+                                })
+                                .transpose()?;
+                            let time = seconds(&change.time, format!("{field}.time"))?;
+                            if matches!(change.anchor, Anchor::Scenario) && time < phase_start {
+                                return Err(error(
+                                    Some(change.time.span()),
+                                    format!("{field}.time"),
+                                    format!(
+                                        "is before the phase starts at {}s, use `anchor = \"phase\"` to measure it from there",
+                                        phase_start.as_secs_f32()
+                                    ),
+                                ));
+                            }
+                            Ok(MachineStateChange {
+                                time,
+                                anchor: change.anchor,
+                                state: change.state.clone(),
+                                pos: change.machine,
+                                duration,
+                                repeat,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                });
+            }
+            let mut reliability = Vec::new();
+            for (j, def) in scenario.reliability.iter().enumerate() {
+                for (name, mean) in [("mtbf", &def.mtbf), ("mttr", &def.mttr)] {
+                    let field = format!("scenarios[{i}].reliability[{j}].{name}");
+                    let seconds = seconds(mean, field.clone())?;
+                    if seconds < MIN_BREAKDOWN_TIME {
+                        return Err(error(
+                            Some(mean.span()),
+                            field,
+                            format!("must be at least {}ms", MIN_BREAKDOWN_TIME.as_millis()),
+                        ));
+                    }
+                    def.distribution
+                        .with_mean(*mean.get_ref())
+                        .check()
+                        .map_err(|message| error(Some(mean.span()), field, message))?;
+                }
+                reliability.push(Reliability {
+                    pos: def.machine,
+                    time_between_failures: def.distribution.with_mean(*def.mtbf.get_ref()),
+                    time_to_repair: def.distribution.with_mean(*def.mttr.get_ref()),
+                });
+            }
+            let mut orders = Vec::new();
+            for (j, order) in scenario.orders.iter().enumerate() {
+                let field = format!("scenarios[{i}].orders[{j}]");
+                let plan = plans
+                    .get(order.plan.get_ref().as_str())
+                    .cloned()
+                    .ok_or_else(|| {
+                        error(
+                            Some(order.plan.span()),
+                            format!("{field}.plan"),
+                            format!("unknown plan `{}`", order.plan.get_ref()),
+                        )
+                    })?;
+                orders.push(Order {
+                    name: order.name.clone().unwrap_or_else(|| (j + 1).to_string()),
+                    plan,
+                    quantity: order.quantity,
+                    release: order
+                        .release
+                        .as_ref()
+                        .map(|release| seconds(release, format!("{field}.release")))
+                        .transpose()?
+                        .unwrap_or_default(),
+                    due: order
+                        .due
+                        .as_ref()
+                        .map(|due| seconds(due, format!("{field}.due")))
+                        .transpose()?,
+                });
+            }
+            let mut result = Scenario::new(scenario.name.clone(), phases);
+            result.orders = orders;
+            result.release_policy = scenario.release;
+            result.layout = layout.clone();
+            result.reliability = reliability;
+            result.seed = scenario.seed;
+            result.dispatch_rule = scenario.dispatching;
+            Ok(result)
+        })
+        .collect()
+}
+
+fn named_color(name: &str) -> Option<Srgb> {
+    match name {
+        "green" => Some(GREEN),
+        "yellow" => Some(YELLOW),
+        "red" => Some(RED),
+        "blue" => Some(BLUE),
+        "magenta" => Some(MAGENTA),
+        _ => None,
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"
+[plans.top]
+color = "blue"
+steps = [
+    { time = 1.0, machine = [0, 1] },
+    { time = 1.0, machine = [2, 1] },
+]
+"#;
+
+    fn load(scenarios: &str) -> Result<Vec<Scenario>, LoadError> {
+        parse_scenarios(Path::new("test.toml"), &format!("{PLAN}{scenarios}"))
+    }
+
+    /// Line, field and message of the error loading the scenarios
+    fn error(scenarios: &str) -> (Option<usize>, String, String) {
+        let err = load(scenarios)
+            .err()
+            .expect("the scenarios should not load");
+        assert_eq!(err.file, Path::new("test.toml"));
+        (err.line, err.field, err.message)
+    }
+
+    #[test]
+    fn loads_phases_with_routed_steps() {
+        let scenarios = load(
+            r#"
+[[scenarios]]
+name = "Normal"
+
+[[scenarios.phases]]
+name = "Start"
+duration = 10
+plans = ["top"]
+
+[[scenarios.phases]]
+name = "End"
+"#,
+        )
+        .unwrap();
+        assert_eq!(scenarios.len(), 1);
+        let phases = &scenarios[0].phases;
+        assert_eq!(phases[0].duration, Some(Duration::from_secs(10)));
+        assert_eq!(phases[1].duration, None);
+        let steps = &phases[0].product_plans[0].steps;
+        assert_eq!(steps[1].path(), [[1, 1], [2, 1]]);
+    }
+
+    #[test]
+    fn reports_line_and_field_of_unknown_plan() {
+        let (line, field, message) = error(
+            r#"
+[[scenarios]]
+name = "Normal"
+
+[[scenarios.phases]]
+name = "Start"
+plans = ["top", "bottom"]
+"#,
+        );
+        assert_eq!(line, Some(14));
+        assert_eq!(field, "scenarios[0].phases[0].plans[1]");
+        assert_eq!(message, "unknown plan `bottom`");
+    }
+
+    #[test]
+    fn reports_negative_times() {
+        let (line, field, message) = error(
+            r#"
+[[scenarios]]
+name = "Normal"
+
+[[scenarios.phases]]
+name = "Start"
+duration = -5
+"#,
+        );
+        assert_eq!(line, Some(14));
+        assert_eq!(field, "scenarios[0].phases[0].duration");
+        assert_eq!(
+            message,
+            "must be a finite and not negative number of seconds"
+        );
+    }
+
+    #[test]
+    fn reports_field_of_type_errors() {
+        let (line, field, _) = error(
+            r#"
+[[scenarios]]
+name = "Normal"
+seed = "random"
+
+[[scenarios.phases]]
+name = "Start"
+"#,
+        );
+        assert_eq!(line, Some(11));
+        assert_eq!(field, "scenarios[0].seed");
+    }
+}
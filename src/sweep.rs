@@ -0,0 +1,204 @@
+use std::{
+    fmt::{self, Display},
+    io::Write,
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::{
+    batch::{self, BatchConfig, Statistic},
+    board::Scenario,
+    headless::HeadlessConfig,
+};
+
+/// Grid of board parameters to simulate every combination of
+#[derive(Debug, Clone)]
+pub struct SweepConfig {
+    /// Settings shared by all configurations, its capacities and time scale are
+    /// used where the grid below gives no values
+    pub run: HeadlessConfig,
+    /// Runs per configuration with consecutive seeds
+    pub runs: u32,
+    pub storage_capacities: Vec<u32>,
+    /// Capacities to try for the module at each position
+    pub module_capacities: Vec<([i32; 2], Vec<u32>)>,
+    pub time_scales: Vec<f32>,
+    pub csv: Option<PathBuf>,
+    pub json: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleCapacity {
+    pub pos: [i32; 2],
+    pub capacity: u32,
+}
+
+/// KPIs of one configuration of the grid
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepPoint {
+    pub storage_capacity: u32,
+    pub module_capacities: Vec<ModuleCapacity>,
+    pub time_scale: f32,
+    pub throughput: Statistic,
+    pub average_lead_time: Statistic,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepResult {
+    pub scenario: String,
+    pub duration: f32,
+    pub runs: u32,
+    pub points: Vec<SweepPoint>,
+}
+
+impl SweepConfig {
+    /// Every combination of the grid values as the config of a headless run
+    fn configurations(&self) -> Vec<HeadlessConfig> {
+        let storage_capacities = or_default(&self.storage_capacities, self.run.storage_capacity);
+        let time_scales = or_default(&self.time_scales, self.run.time_scale);
+
+        let mut configurations = Vec::new();
+        for &storage_capacity in &storage_capacities {
+            for &time_scale in &time_scales {
+                configurations.push(HeadlessConfig {
+                    storage_capacity,
+                    time_scale,
+                    ..self.run.clone()
+                });
+            }
+        }
+        for (pos, capacities) in &self.module_capacities {
+            configurations = configurations
+                .into_iter()
+                .flat_map(|config| {
+                    capacities.iter().map(move |&capacity| {
+                        let mut config = config.clone();
+                        config.module_capacities.push((*pos, capacity));
+                        config
+                    })
+                })
+                .collect();
+        }
+        configurations
+    }
+}
+
+fn or_default<T: Copy>(values: &[T], default: T) -> Vec<T> {
+    if values.is_empty() {
+        vec![default]
+    } else {
+        values.to_vec()
+    }
+}
+
+/// Simulate every configuration of the grid `config.runs` times
+pub fn run(scenarios: &[Scenario], config: &SweepConfig) -> Result<SweepResult, String> {
+    let mut points = Vec::new();
+    let mut scenario = String::new();
+    for run in config.configurations() {
+        let result = batch::run(
+            scenarios,
+            &BatchConfig {
+                run: run.clone(),
+                runs: config.runs,
+                csv: None,
+                json: None,
+            },
+        )?;
+        let statistic = |kpi| {
+            result
+                .statistic(kpi)
+                .cloned()
+                .expect("Every batch summarizes the throughput and lead time")
+        };
+        points.push(SweepPoint {
+            storage_capacity: run.storage_capacity,
+            module_capacities: run
+                .module_capacities
+                .iter()
+                .map(|&(pos, capacity)| ModuleCapacity { pos, capacity })
+                .collect(),
+            time_scale: run.time_scale,
+            throughput: statistic("throughput"),
+            average_lead_time: statistic("average_lead_time"),
+        });
+        scenario = result.scenario;
+    }
+    Ok(SweepResult {
+        scenario,
+        duration: config.run.duration.as_secs_f32(),
+        runs: config.runs,
+        points,
+    })
+}
+
+fn format_capacities(capacities: &[ModuleCapacity]) -> String {
+    capacities
+        .iter()
+        .map(|module| format!("{},{}={}", module.pos[0], module.pos[1], module.capacity))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl SweepResult {
+    /// One line per configuration with the mean and 95% confidence interval of each KPI
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "storage_capacity,module_capacities,time_scale,throughput,throughput_ci95_low,throughput_ci95_high,average_lead_time,average_lead_time_ci95_low,average_lead_time_ci95_high"
+        )?;
+        for point in &self.points {
+            writeln!(
+                writer,
+                "{},\"{}\",{},{},{},{},{},{},{}",
+                point.storage_capacity,
+                format_capacities(&point.module_capacities),
+                point.time_scale,
+                point.throughput.mean,
+                point.throughput.ci95_low,
+                point.throughput.ci95_high,
+                point.average_lead_time.mean,
+                point.average_lead_time.ci95_low,
+                point.average_lead_time.ci95_high
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, writer: impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+}
+
+impl Display for SweepResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} configurations of {} over {:.0}s, {} run(s) each:",
+            self.points.len(),
+            self.scenario,
+            self.duration,
+            self.runs
+        )?;
+        writeln!(
+            f,
+            "{:>7} {:>6} {:>14} {:>14}  modules",
+            "storage", "time", "throughput", "lead time"
+        )?;
+        for point in &self.points {
+            writeln!(
+                f,
+                "{:>7} {:>6.2} {:>8.2} ±{:<5.2} {:>7.1}s ±{:<5.1}  {}",
+                point.storage_capacity,
+                point.time_scale,
+                point.throughput.mean,
+                point.throughput.ci95_high - point.throughput.mean,
+                point.average_lead_time.mean,
+                point.average_lead_time.ci95_high - point.average_lead_time.mean,
+                format_capacities(&point.module_capacities)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -4,7 +4,9 @@ use std::{
     time::{Duration, Instant},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct VirtualInstant {
     elapsed: Duration,
 }
@@ -43,6 +45,15 @@ impl Sub<VirtualInstant> for VirtualInstant {
     }
 }
 
+/// Where the virtual time of a [`TimeManager`] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// Follows the wall clock, scaled by the speed multiplier
+    RealTime,
+    /// Only advances by the deltas passed to [`TimeManager::advance`]
+    Manual,
+}
+
 /// A time manager that provides independent time control for the simulation
 /// Allows speeding up, slowing down, and pausing the simulation
 #[derive(Debug, Clone)]
@@ -51,12 +62,13 @@ pub struct TimeManager {
 }
 #[derive(Debug)]
 struct TimeManagerInner {
+    clock_source: ClockSource,
+    /// Virtual time queued by `advance` for the next update
+    pending_virtual_delta: Duration,
     /// Real time when the manager was created or last reset
     start_real_time: Instant,
     /// Virtual time elapsed since start
     virtual_instance: VirtualInstant,
-    /// Last virtual delta time
-    last_virtual_delta: Duration,
     /// Speed multiplier (1.0 = normal speed, 2.0 = double speed, 0.5 = half speed)
     speed_multiplier: f64,
     last_speed_multiplier: f64,
@@ -67,12 +79,17 @@ struct TimeManagerInner {
 impl TimeManager {
     /// Create a new TimeManager with normal speed (1.0x)
     pub fn new() -> Self {
+        Self::with_clock_source(ClockSource::RealTime)
+    }
+
+    pub fn with_clock_source(clock_source: ClockSource) -> Self {
         let now = Instant::now();
         Self {
             inner: Arc::new(Mutex::new(TimeManagerInner {
+                clock_source,
+                pending_virtual_delta: Duration::ZERO,
                 start_real_time: now,
                 virtual_instance: VirtualInstant::zero(),
-                last_virtual_delta: Duration::ZERO,
                 speed_multiplier: 1.0,
                 last_speed_multiplier: 1.0,
                 last_update: now,
@@ -85,26 +102,33 @@ impl TimeManager {
         self.inner.lock().unwrap().virtual_instance
     }
 
-    /// Get the last virtual delta time in seconds
-    pub fn last_virtual_delta(&self) -> f32 {
-        self.inner.lock().unwrap().last_virtual_delta.as_secs_f32()
-    }
-
     /// Update the virtual time based on real time and current settings
     /// This should be called once per frame
     pub fn update(&mut self) {
         let mut inner = self.inner.lock().unwrap();
         let now = Instant::now();
 
-        let real_delta = now.duration_since(inner.last_update);
-        let virtual_delta =
-            Duration::from_secs_f64(real_delta.as_secs_f64() * inner.speed_multiplier);
-        inner.last_virtual_delta = virtual_delta;
+        let virtual_delta = match inner.clock_source {
+            ClockSource::RealTime if inner.pending_virtual_delta.is_zero() => {
+                let real_delta = now.duration_since(inner.last_update);
+                Duration::from_secs_f64(real_delta.as_secs_f64() * inner.speed_multiplier)
+            }
+            ClockSource::RealTime | ClockSource::Manual => {
+                std::mem::take(&mut inner.pending_virtual_delta)
+            }
+        };
         inner.virtual_instance += virtual_delta;
 
         inner.last_update = now;
     }
 
+    /// Queue a virtual delta for the next update
+    /// The speed multiplier is not applied, the delta is taken as is. A real time
+    /// clock takes it instead of the wall time passed, to step it deterministically.
+    pub fn advance(&mut self, virtual_delta: Duration) {
+        self.inner.lock().unwrap().pending_virtual_delta += virtual_delta;
+    }
+
     /// Set the speed multiplier
     /// - 1.0 = normal speed
     /// - 2.0 = double speed
@@ -124,9 +148,18 @@ impl TimeManager {
         let now = Instant::now();
         inner.start_real_time = now;
         inner.virtual_instance = VirtualInstant::zero();
+        inner.pending_virtual_delta = Duration::ZERO;
         inner.last_update = now;
     }
 
+    /// Continue from the given virtual time, e.g. of a restored snapshot
+    pub fn set_now(&mut self, now: VirtualInstant) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.virtual_instance = now;
+        inner.pending_virtual_delta = Duration::ZERO;
+        inner.last_update = Instant::now();
+    }
+
     /// Get the virtual time as a formatted string (MM:SS.mmm)
     pub fn format_time(&self) -> String {
         let total_secs = self
@@ -143,6 +176,10 @@ impl TimeManager {
 
     pub fn pause(&mut self) {
         let mut inner = self.inner.lock().unwrap();
+        if inner.speed_multiplier == 0.0 {
+            // Already paused, keep the speed to resume to
+            return;
+        }
         inner.last_speed_multiplier = inner.speed_multiplier;
         inner.speed_multiplier = 0.0;
     }
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use macroquad::prelude::*;
+
+use crate::{
+    board::{Board, Snapshot},
+    constants::{DASHBOARD_WIDTH, PIXEL_PER_MODULE, TIMELINE_HEIGHT, X_NUM_MODULES, Y_NUM_MODULES},
+    time_manager::VirtualInstant,
+};
+
+/// Virtual time between two snapshots kept to go back to
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+/// Snapshots kept before every other one is dropped, each holds the metrics so far
+const MAX_SNAPSHOTS: usize = 128;
+/// Pixels between the bar and the top and sides of the timeline area
+const MARGIN: f32 = 10.0;
+const BAR_HEIGHT: f32 = 8.0;
+
+/// Snapshots of the running scenario, to drag back to any earlier virtual time
+/// and continue from there
+#[derive(Default)]
+pub struct Timeline {
+    snapshots: Vec<Snapshot>,
+    /// How often the snapshots were thinned out, each time doubling the interval
+    thinned: u32,
+    scenario: String,
+    /// Time of the last update, the scenario restarted if the time went back
+    last_time: VirtualInstant,
+    dragging: bool,
+}
+
+impl Timeline {
+    /// Keep a snapshot every `SNAPSHOT_INTERVAL`, twice as far apart each time
+    /// there are more than `MAX_SNAPSHOTS`. Once the time moves on after a
+    /// seek the snapshots ahead of it are dropped, they show a run that is gone.
+    pub fn record(&mut self, board: &Board) {
+        let now = board.time_manager.now();
+        if board.scenario_name() != self.scenario || now < self.last_time {
+            self.snapshots.clear();
+            self.thinned = 0;
+            self.scenario = board.scenario_name().to_string();
+        } else if now > self.last_time {
+            self.snapshots.retain(|snapshot| snapshot.time <= now);
+        }
+        let interval = SNAPSHOT_INTERVAL * 2u32.pow(self.thinned);
+        // After a seek with the time standing the snapshots ahead are kept, and
+        // nothing is recorded until the time runs on
+        if self
+            .snapshots
+            .last()
+            .is_none_or(|snapshot| now.inner().saturating_sub(snapshot.time.inner()) >= interval)
+        {
+            self.snapshots.push(board.snapshot());
+            if self.snapshots.len() > MAX_SNAPSHOTS {
+                let mut index = 0;
+                self.snapshots.retain(|_| {
+                    index += 1;
+                    index % 2 == 1
+                });
+                self.thinned += 1;
+            }
+        }
+        self.last_time = now;
+    }
+
+    /// Restore the last snapshot before `time` and simulate from there until `time`,
+    /// the same way every time
+    pub fn seek(&mut self, board: &mut Board, time: VirtualInstant) {
+        let Some(snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.time <= time)
+        else {
+            return;
+        };
+        // Everything up to `time` was published when the scenario first ran, so
+        // the subscribers only hear from the board again once it runs on
+        let events = std::mem::take(&mut board.events);
+        match board.restore(board.scenario().clone(), snapshot.clone()) {
+            Ok(()) => {
+                // Positions follow from the virtual time, so one step gets there
+                let step = (time - board.time_manager.now()).inner();
+                board.time_manager.advance(step);
+                board.advance();
+            }
+            Err(err) => eprintln!("Failed to seek: {err}"),
+        }
+        board.events = events;
+        self.last_time = board.time_manager.now();
+    }
+
+    /// Latest time that can be sought
+    fn end(&self, board: &Board) -> VirtualInstant {
+        let now = board.time_manager.now();
+        self.snapshots
+            .last()
+            .map_or(now, |snapshot| snapshot.time.max(now))
+    }
+
+    /// Seek to the time under the mouse while the bar is dragged
+    pub fn handle_input(&mut self, board: &mut Board) {
+        let area = area();
+        let (x, y) = mouse_position();
+        if is_mouse_button_pressed(MouseButton::Left) && area.contains(vec2(x, y)) {
+            self.dragging = true;
+        }
+        if !is_mouse_button_down(MouseButton::Left) {
+            self.dragging = false;
+        }
+        if !self.dragging {
+            return;
+        }
+        let bar = bar(area);
+        let fraction = ((x - bar.x) / bar.w).clamp(0.0, 1.0);
+        let end = self.end(board).inner();
+        let time = VirtualInstant::zero() + end.mul_f32(fraction);
+        // Holding the mouse still lets the scenario run on from there
+        let now = board.time_manager.now().inner();
+        if time.inner().abs_diff(now) > end.div_f32(bar.w) {
+            self.seek(board, time);
+        }
+    }
+
+    /// Bar below the board from the scenario start to the latest time that can be
+    /// sought, with the phase starts and the current time
+    pub fn draw(&self, board: &Board) {
+        let area = area();
+        draw_rectangle(area.x, area.y, area.w, area.h, DARKGRAY);
+        let bar = bar(area);
+        let end = self.end(board).inner().as_secs_f32().max(1.0);
+        let to_x = |time: VirtualInstant| bar.x + time.inner().as_secs_f32() / end * bar.w;
+
+        let now = to_x(board.time_manager.now());
+        draw_rectangle(bar.x, bar.y, bar.w, bar.h, GRAY);
+        draw_rectangle(bar.x, bar.y, now - bar.x, bar.h, SKYBLUE);
+        for phase in board.metrics.phases.iter().skip(1) {
+            let x = to_x(phase.start);
+            draw_line(x, bar.y - 4.0, x, bar.y + bar.h + 4.0, 1.0, YELLOW);
+        }
+        draw_circle(now, bar.y + bar.h / 2.0, BAR_HEIGHT, WHITE);
+        draw_text(
+            &board.time_manager.format_time(),
+            bar.x,
+            area.y + area.h - 2.0,
+            14.0,
+            LIGHTGRAY,
+        );
+    }
+}
+
+fn area() -> Rect {
+    Rect::new(
+        0.0,
+        Y_NUM_MODULES as f32 * PIXEL_PER_MODULE,
+        X_NUM_MODULES as f32 * PIXEL_PER_MODULE + DASHBOARD_WIDTH,
+        TIMELINE_HEIGHT,
+    )
+}
+
+fn bar(area: Rect) -> Rect {
+    Rect::new(
+        area.x + MARGIN,
+        area.y + MARGIN,
+        area.w - 2.0 * MARGIN,
+        BAR_HEIGHT,
+    )
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use crate::{
+    board::{Board, Scenario},
+    product::ProductPlan,
+};
+
+/// Where in the scenarios a problem was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Plan {
+        plan: String,
+        step: Option<usize>,
+    },
+    MachineStateChange {
+        scenario: String,
+        phase: String,
+        change: usize,
+    },
+    Reliability {
+        scenario: String,
+        index: usize,
+    },
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Plan { plan, step: None } => write!(f, "plan `{plan}`"),
+            Location::Plan {
+                plan,
+                step: Some(step),
+            } => write!(f, "plan `{plan}`, step {step}"),
+            Location::MachineStateChange {
+                scenario,
+                phase,
+                change,
+            } => write!(
+                f,
+                "scenario `{scenario}`, phase `{phase}`, machine state change {change}"
+            ),
+            Location::Reliability { scenario, index } => {
+                write!(f, "scenario `{scenario}`, reliability {index}")
+            }
+        }
+    }
+}
+
+/// One problem that would make a scenario fail or misbehave at runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub location: Location,
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check the scenarios and all their product plans, returning every problem found
+pub fn validate(scenarios: &[Scenario]) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+
+    let plans = scenarios
+        .iter()
+        .flat_map(Scenario::product_plans)
+        .map(|plan| (plan.name.as_str(), plan))
+        .collect::<BTreeMap<_, _>>();
+    for plan in plans.values() {
+        validate_plan(plan, &mut errors);
+    }
+
+    for scenario in scenarios {
+        for phase in &scenario.phases {
+            for (change, machine_state_change) in phase.machine_state_changes.iter().enumerate() {
+                let pos = machine_state_change.pos;
+                if !Board::inbounds(pos) {
+                    errors.push(ValidationError {
+                        location: Location::MachineStateChange {
+                            scenario: scenario.name.clone(),
+                            phase: phase.name.clone(),
+                            change,
+                        },
+                        message: format!("machine {pos:?} is not on the board"),
+                    });
+                }
+            }
+        }
+        for (index, reliability) in scenario.reliability.iter().enumerate() {
+            let pos = reliability.pos;
+            if !Board::inbounds(pos) {
+                errors.push(ValidationError {
+                    location: Location::Reliability {
+                        scenario: scenario.name.clone(),
+                        index,
+                    },
+                    message: format!("machine {pos:?} is not on the board"),
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn validate_plan(plan: &ProductPlan, errors: &mut Vec<ValidationError>) {
+    let mut error = |step, message| {
+        errors.push(ValidationError {
+            location: Location::Plan {
+                plan: plan.name.clone(),
+                step,
+            },
+            message,
+        })
+    };
+
+    if plan.steps.len() < 2 {
+        error(None, "a product plan needs at least 2 steps".to_string());
+    }
+
+    let mut previous = None;
+    for (i, step) in plan.steps.iter().enumerate() {
+        for machine in step.machines().skip(1) {
+            if !Board::inbounds(machine) {
+                error(
+                    Some(i),
+                    format!("alternative machine {machine:?} is not on the board"),
+                );
+            }
+        }
+        // A new product is shown at the first cell but counted at the machine
+        if i == 0 && step.path().iter().any(|cell| *cell != step.maschine_pos()) {
+            error(
+                Some(i),
+                "the first step starts at its machine and can not have a path".to_string(),
+            );
+        }
+        let mut cells_on_board = true;
+        for cell in step.path() {
+            if !Board::inbounds(cell) {
+                error(Some(i), format!("cell {cell:?} is not on the board"));
+                cells_on_board = false;
+            }
+        }
+        // The light point travels in straight lines only, so every cell of the
+        // path has to be the same as or next to the one before
+        if cells_on_board {
+            let cells = previous.into_iter().chain(step.path()).collect::<Vec<_>>();
+            for pair in cells.windows(2) {
+                let [from, to] = [pair[0], pair[1]];
+                if (from[0] - to[0]).abs() + (from[1] - to[1]).abs() > 1 {
+                    error(
+                        Some(i),
+                        format!("path jumps from {from:?} to {to:?}, cells must be neighbours"),
+                    );
+                }
+            }
+        }
+        previous = Some(step.maschine_pos());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use palette::Srgb;
+
+    use super::*;
+    use crate::{
+        board::{MachineStateChange, ModuleState, Phase},
+        product::Step,
+    };
+
+    fn plan(name: &str, steps: Vec<Step>) -> ProductPlan {
+        ProductPlan::new(name.to_string(), steps, Srgb::new(0.0, 0.0, 1.0))
+    }
+
+    fn scenario(
+        plans: Vec<ProductPlan>,
+        machine_state_changes: Vec<MachineStateChange>,
+    ) -> Scenario {
+        Scenario::new(
+            "Test".to_string(),
+            vec![Phase {
+                name: "Normal".to_string(),
+                duration: None,
+                product_plans: plans,
+                machine_state_changes,
+            }],
+        )
+    }
+
+    fn messages(scenarios: &[Scenario]) -> Vec<String> {
+        validate(scenarios)
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn accepts_plan_along_neighbouring_cells() {
+        let steps = vec![
+            Step::new(1.0, [0, 1], vec![[0, 1]], false),
+            Step::new(1.0, [2, 1], vec![[1, 1]], false),
+            Step::new(1.0, [2, 3], vec![[2, 2]], true),
+        ];
+        assert_eq!(
+            validate(&[scenario(vec![plan("ok", steps)], Vec::new())]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reports_every_problem_of_a_plan() {
+        let steps = vec![
+            Step::new(1.0, [0, 1], vec![[0, 0]], false),
+            Step::new(1.0, [3, 1], Vec::new(), false),
+            Step::new(1.0, [9, 1], vec![[4, 1], [5, 1]], false),
+        ];
+        assert_eq!(
+            messages(&[scenario(vec![plan("bad", steps)], Vec::new())]),
+            [
+                "plan `bad`, step 0: the first step starts at its machine and can not have a path",
+                "plan `bad`, step 1: path jumps from [0, 1] to [3, 1], cells must be neighbours",
+                "plan `bad`, step 2: cell [9, 1] is not on the board",
+            ]
+        );
+    }
+
+    #[test]
+    fn needs_two_steps() {
+        let steps = vec![Step::new(1.0, [0, 1], Vec::new(), false)];
+        assert_eq!(
+            messages(&[scenario(vec![plan("short", steps)], Vec::new())]),
+            ["plan `short`: a product plan needs at least 2 steps"]
+        );
+    }
+
+    #[test]
+    fn reports_machine_state_changes_off_the_board() {
+        let change = MachineStateChange {
+            time: Duration::from_secs(1),
+            anchor: Default::default(),
+            state: ModuleState::Broken,
+            pos: [6, 0],
+            duration: None,
+            repeat: None,
+        };
+        assert_eq!(
+            messages(&[scenario(Vec::new(), vec![change])]),
+            [
+                "scenario `Test`, phase `Normal`, machine state change 0: machine [6, 0] is not on the board"
+            ]
+        );
+    }
+}